@@ -0,0 +1,218 @@
+use std::path::Path;
+
+use crate::header::restored_mode;
+
+/// File stored in an archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+	/// Relative path, with `/` separators
+	pub path: String,
+	pub size: u64,
+	/// Unix mode bits, 0 when unknown
+	pub mode: u32,
+	/// BLAKE3 hash of the file
+	pub checksum: [u8; 32],
+}
+
+impl Entry {
+	/// Verifies the file's data against its checksum
+	pub fn verify(&self, data: &[u8]) -> Result<(), String> {
+		if blake3::hash(data).as_bytes() != &self.checksum {
+			return Err(format!(
+				"Checksum of `{}` does not match, the file is damaged",
+				self.path
+			));
+		}
+		Ok(())
+	}
+
+	/// Gets the permissions to restore on the extracted file, given those of the newly `created`
+	/// file, see [`restored_mode`]
+	pub fn restored_mode(&self, created: u32) -> Option<u32> {
+		(self.mode != 0).then(|| restored_mode(self.mode, created))
+	}
+}
+
+/// Checks that an archive path stays inside of the extraction directory
+pub fn check_path(path: &str) -> Result<(), String> {
+	if path.is_empty()
+		|| path.starts_with('/')
+		|| path.contains('\\')
+		|| path.contains('\0')
+		|| path.len() > u16::MAX as usize
+		|| path
+			.split('/')
+			.any(|component| matches!(component, "" | "." | ".."))
+	{
+		return Err(format!("Invalid archive path `{path}`"));
+	}
+	Ok(())
+}
+
+/// Several files stored as one payload: their data follows each other in the order of the file
+/// table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Archive {
+	pub entries: Vec<Entry>,
+}
+
+impl Archive {
+	/// Reads files and directories into an archive, returning its data and file table.
+	/// Directories keep their name as the first component of their files' paths.
+	pub fn build(paths: &[String]) -> Result<(Vec<u8>, Self), String> {
+		let mut data = vec![];
+		let mut archive = Self { entries: vec![] };
+		for path in paths {
+			let name = Path::new(path)
+				.file_name()
+				.ok_or(format!("Can not archive `{path}`, it has no name"))?
+				.to_str()
+				.ok_or(format!("Can not archive `{path}`, its name is not UTF-8"))?;
+			archive.add(Path::new(path), name.to_string(), &mut data)?;
+		}
+		if archive.entries.is_empty() {
+			return Err("No files to archive".into());
+		}
+		Ok((data, archive))
+	}
+
+	/// Adds a file or the content of a directory
+	fn add(&mut self, path: &Path, name: String, data: &mut Vec<u8>) -> Result<(), String> {
+		let metadata = std::fs::symlink_metadata(path)
+			.map_err(|err| format!("Failed to read `{}` metadata: {err}", path.display()))?;
+		if metadata.is_dir() {
+			let mut children = std::fs::read_dir(path)
+				.map_err(|err| format!("Failed to read directory `{}`: {err}", path.display()))?
+				.map(|entry| entry.map(|entry| entry.file_name()))
+				.collect::<Result<Vec<_>, _>>()
+				.map_err(|err| format!("Failed to read directory `{}`: {err}", path.display()))?;
+			children.sort();
+			for child in children {
+				let child_name = child.to_str().ok_or(format!(
+					"Can not archive `{}`, its name is not UTF-8",
+					path.join(&child).display()
+				))?;
+				self.add(&path.join(&child), format!("{name}/{child_name}"), data)?;
+			}
+			return Ok(());
+		}
+		if !metadata.is_file() {
+			eprintln!("Skipping `{}`, not a regular file", path.display());
+			return Ok(());
+		}
+
+		check_path(name.as_str())?;
+		if self.entries.iter().any(|entry| entry.path == name) {
+			return Err(format!("`{name}` is given more than once"));
+		}
+		let file = std::fs::read(path)
+			.map_err(|err| format!("Failed to read input file `{}`: {err}", path.display()))?;
+		#[cfg(unix)]
+		let mode = {
+			use std::os::unix::fs::PermissionsExt;
+			metadata.permissions().mode() & 0o7777
+		};
+		#[cfg(not(unix))]
+		let mode = 0;
+		self.entries.push(Entry {
+			path: name,
+			size: file.len() as u64,
+			mode,
+			checksum: *blake3::hash(&file).as_bytes(),
+		});
+		data.extend_from_slice(file.as_slice());
+		Ok(())
+	}
+
+	/// Gets the total size of the files
+	pub fn size(&self) -> u64 {
+		self.entries
+			.iter()
+			.fold(0u64, |size, entry| size.saturating_add(entry.size))
+	}
+
+	/// Splits the archive data in the data of each file
+	pub fn files<'a>(&'a self, data: &'a [u8]) -> Result<Vec<(&'a Entry, &'a [u8])>, String> {
+		if self.size() != data.len() as u64 {
+			return Err(format!(
+				"Archive files total {}bytes but the payload is {}bytes",
+				self.size(),
+				data.len()
+			));
+		}
+		let mut data = data;
+		Ok(self
+			.entries
+			.iter()
+			.map(|entry| {
+				let (file, rest) = data.split_at(entry.size as usize);
+				data = rest;
+				(entry, file)
+			})
+			.collect())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn entry(path: &str, data: &[u8]) -> Entry {
+		Entry {
+			path: path.into(),
+			size: data.len() as u64,
+			mode: 0o644,
+			checksum: *blake3::hash(data).as_bytes(),
+		}
+	}
+
+	#[test]
+	fn files() {
+		let archive = Archive {
+			entries: vec![entry("dir/a.txt", b"first"), entry("dir/sub/b", b"second")],
+		};
+		assert_eq!(archive.size(), 11);
+		let files = archive.files(b"firstsecond").unwrap();
+		assert_eq!(files[0].1, b"first");
+		assert_eq!(files[1].1, b"second");
+		assert!(files[1].0.verify(files[1].1).is_ok());
+		assert!(files[0].0.verify(b"other").is_err());
+		assert!(archive.files(b"first").is_err());
+		assert!(archive.files(b"firstsecond!").is_err());
+	}
+
+	#[test]
+	fn paths() {
+		for path in ["a", "dir/a.txt", "a/.b", "a..b"] {
+			assert!(check_path(path).is_ok(), "{path}");
+		}
+		for path in [
+			"",
+			"/etc/passwd",
+			"../a",
+			"a/../../b",
+			"a//b",
+			"a\\b",
+			"a/",
+			"./a",
+			"a\0",
+		] {
+			assert!(check_path(path).is_err(), "{path}");
+		}
+	}
+
+	#[test]
+	fn restored_modes() {
+		let mut entry = Entry {
+			path: "file".into(),
+			size: 0,
+			mode: 0o6755,
+			checksum: [0; 32],
+		};
+		assert_eq!(entry.restored_mode(0o644), Some(0o755));
+		entry.mode = 0o1777;
+		assert_eq!(entry.restored_mode(0o600), Some(0o700));
+		entry.mode = 0;
+		assert_eq!(entry.restored_mode(0o644), None);
+	}
+}
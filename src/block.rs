@@ -1,10 +1,17 @@
+use std::ops::Range;
+
 use bitvec::slice::BitSlice;
 use bitvec::vec::BitVec;
 use rand::prelude::SliceRandom;
 use rand::Rng;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
 
 use crate::embed::EmbedAlgorithm;
 use crate::ent::EntropyGenerator;
+use crate::header::Decode;
+use crate::header::Header;
+use crate::header::Version;
 
 /// Gets the best blocksize (i.e. that minimize remaining space) for a certain data length.
 /// The blocksize is a number in range [16, 65536]
@@ -22,60 +29,158 @@ pub fn best_blocksize(len: usize) -> usize {
 	1 << best_p
 }
 
+/// Gets the shuffled order of the blocks in the data
+pub fn block_order<R>(data_len: usize, block_size: usize, rng: &mut R) -> Vec<usize>
+where
+	R: Rng + ?Sized,
+{
+	let mut blocks = (0..data_len / block_size).collect::<Vec<_>>();
+	blocks.shuffle(rng);
+	blocks
+}
+
+/// Gets the data spans of a range of the stream formed by the blocks, in order
+pub fn block_spans(order: &[usize], block_size: usize, range: Range<usize>) -> Vec<Range<usize>> {
+	let mut spans = vec![];
+	let mut pos = range.start;
+	while pos < range.end {
+		let offset = pos % block_size;
+		let len = (block_size - offset).min(range.end - pos);
+		let start = order[pos / block_size] * block_size + offset;
+		spans.push(start..start + len);
+		pos += len;
+	}
+	spans
+}
+
+/// Positions in the block stream of the second and third header copies since
+/// [`Version::VERSION_2`], the first copy starting the embed
+pub fn header_copies(stream_len: usize, copy_len: usize) -> Result<[Range<usize>; 2], String> {
+	if stream_len < copy_len * 3 {
+		return Err(format!(
+			"Image too small to hold the header copies: {}bytes required, maximum: {stream_len}bytes",
+			copy_len * 3
+		));
+	}
+	Ok([
+		stream_len / 3..stream_len / 3 + copy_len,
+		stream_len * 2 / 3..stream_len * 2 / 3 + copy_len,
+	])
+}
+
+/// Gets the data spans holding the embed, i.e. the whole block stream except the header copies
+pub fn embed_spans(
+	order: &[usize],
+	block_size: usize,
+	copies: &[Range<usize>; 2],
+) -> Vec<Range<usize>> {
+	let stream_len = order.len() * block_size;
+	[
+		0..copies[0].start,
+		copies[0].end..copies[1].start,
+		copies[1].end..stream_len,
+	]
+	.into_iter()
+	.flat_map(|range| block_spans(order, block_size, range))
+	.collect()
+}
+
+/// Decodes the first valid header copy embedded in the image data
+///
+/// Returns the header and an iterator positioned at the start of the payload
+pub fn read_header<'a>(
+	algorithm: &'a EmbedAlgorithm,
+	data: &'a [u8],
+	seed: [u8; 32],
+) -> Result<(Header, BlockPlacementIterator<'a>), String> {
+	let block_size = best_blocksize(data.len());
+	let mut rand = ChaCha8Rng::from_seed(seed);
+	let order = block_order(data.len(), block_size, &mut rand);
+
+	let stream_len = order.len() * block_size;
+	let mut errors = vec![];
+	for copy in 0..3 {
+		let blocks = block_spans(&order, block_size, copy * stream_len / 3..stream_len);
+		let mut it = BlockPlacementIterator::new(algorithm, data, blocks);
+		let header = match Header::decode(&mut it) {
+			Ok(header) => header,
+			Err(err) => {
+				errors.push(err);
+				continue;
+			}
+		};
+
+		// VERSION_1 images only hold the first copy
+		if header.version == Version::VERSION_1 {
+			if copy != 0 {
+				continue;
+			}
+			return Ok((header, it));
+		}
+		if copy != 0 {
+			eprintln!("Warning: damaged header, using copy #{}", copy + 1);
+		}
+
+		let header_len = it.read();
+		let copies = header_copies(stream_len, algorithm.embedded_size(header_len))?;
+		let mut it =
+			BlockPlacementIterator::new(algorithm, data, embed_spans(&order, block_size, &copies));
+		for _ in 0..header_len {
+			it.next();
+		}
+		return Ok((header, it));
+	}
+
+	Err(errors.swap_remove(0))
+}
+
 /// Struct to hold the positions of data blocks
 #[derive(Debug)]
 pub struct BlockPlacement<'a> {
 	algorithm: &'a EmbedAlgorithm,
 	data: &'a mut [u8],
-	block_size: usize,
 	pub used_blocks: usize,
-	pub blocks: Vec<usize>,
+	pub blocks: Vec<Range<usize>>,
 }
 
 impl<'a> BlockPlacement<'a> {
-	// Attempts to create a new block placement
+	// Attempts to create a new block placement over the given data spans, in order
 	//
 	// # Errors
 	//
-	// Will fail if the data is too small to hold all the blocks
-	pub fn new<R>(
+	// Will fail if the spans are too small to hold the embed
+	pub fn new(
 		algorithm: &'a EmbedAlgorithm,
 		data: &'a mut [u8],
-		block_size: usize,
 		embed_size: usize,
-		rng: &mut R,
-	) -> Result<Self, String>
-	where
-		R: Rng + ?Sized,
-	{
+		blocks: Vec<Range<usize>>,
+	) -> Result<Self, String> {
 		// Total size of the embed (crc included)
 		let embedded_size = algorithm.embedded_size(embed_size);
 
-		// Maximum number of blocks
-		let max_blocks = data.len() / block_size;
+		// Available size
+		let max_size = blocks.iter().map(|block| block.len()).sum::<usize>();
 
-		// Number of blocks
-		let blocks_num = (embedded_size as f64 / block_size as f64).ceil() as usize;
-
-		if blocks_num > max_blocks {
+		if embedded_size > max_size {
 			return Err(format!(
-				"Too many blocks required: {blocks_num}, maximum: {max_blocks}"
+				"Embed too large: {embedded_size}bytes required, maximum: {max_size}bytes"
 			));
 		}
 
-		// Blocks in the resulting data
-		let mut blocks = (0..max_blocks).collect::<Vec<_>>();
-
-		// Shuffle the block order
-		blocks.shuffle(rng);
-
-		// Only keep the first blocks_num blocks
-		//blocks.resize(blocks_num, 0);
+		// Number of blocks
+		let mut size = 0;
+		let blocks_num = blocks
+			.iter()
+			.take_while(|block| {
+				let used = size < embedded_size;
+				size += block.len();
+				used
+			})
+			.count();
 
 		let s = Self {
 			algorithm,
 			data,
-			block_size,
 			used_blocks: blocks_num,
 			blocks,
 		};
@@ -98,8 +203,7 @@ impl<'a> BlockPlacement<'a> {
 		match self.algorithm {
 			EmbedAlgorithm::Lo(bits) => {
 				for block in &self.blocks[0..self.used_blocks] {
-					for i in 0..self.block_size {
-						let pos = block * self.block_size + i;
+					for pos in block.clone() {
 						let hi = std::cmp::min(*bits as usize, embed.len() - index);
 
 						self.data[pos] &= !((1 << hi) - 1);
@@ -121,11 +225,9 @@ impl<'a> BlockPlacement<'a> {
 			EmbedAlgorithm::Lo(bits) => {
 				let mask: u8 = (1 << bits) - 1;
 				for block in &self.blocks[self.used_blocks..] {
-					for i in 0..self.block_size {
-						let pos = block * self.block_size + i;
-
+					for pos in block.clone() {
 						self.data[pos] &= !mask;
-						self.data[pos] |= gen.next() & mask;
+						self.data[pos] |= gen.next_byte() & mask;
 					}
 				}
 			}
@@ -137,45 +239,36 @@ impl<'a> BlockPlacement<'a> {
 pub struct BlockPlacementIterator<'a> {
 	algorithm: &'a EmbedAlgorithm,
 	data: &'a [u8],
-	block_size: usize,
 
 	// Block index
 	index: usize,
-	// Position of the blocks
-	blocks: Vec<usize>,
+	// Number of bytes read
+	read: usize,
+	// Data spans of the blocks
+	blocks: Vec<Range<usize>>,
 	// Iterator over the current block
 	block_it: Option<BlockIterator<'a>>,
 }
 
 impl<'a> BlockPlacementIterator<'a> {
-	/// Creates a new embed iterator
-	pub fn new<R: Rng + ?Sized>(
-		algorithm: &'a EmbedAlgorithm,
-		data: &'a [u8],
-		block_size: usize,
-		rng: &mut R,
-	) -> Self {
-		// Maximum number of blocks
-		let max_blocks = data.len() / block_size;
-
-		// Blocks
-		let mut blocks = (0..max_blocks).collect::<Vec<_>>();
-
-		// Shuffle the block order
-		blocks.shuffle(rng);
-
-		let first_block_pos = blocks[0] * block_size;
-		let first_block = &data[first_block_pos..first_block_pos + block_size];
+	/// Creates a new embed iterator over the given data spans, in order
+	pub fn new(algorithm: &'a EmbedAlgorithm, data: &'a [u8], blocks: Vec<Range<usize>>) -> Self {
+		let block_it = blocks
+			.first()
+			.map(|block| BlockIterator::new(Block(algorithm, &data[block.clone()]), None));
 
 		Self {
 			algorithm,
 			data,
-			block_size,
 			index: 0,
+			read: 0,
 			blocks,
-			block_it: Some(BlockIterator::new(Block(algorithm, first_block), None)),
+			block_it,
 		}
 	}
+
+	/// Number of bytes read so far
+	pub fn read(&self) -> usize { self.read }
 }
 
 impl<'a> Iterator for BlockPlacementIterator<'a> {
@@ -191,6 +284,7 @@ impl<'a> Iterator for BlockPlacementIterator<'a> {
 		self.block_it.as_ref()?;
 
 		if let Some(byte) = self.block_it.as_mut().unwrap().next() {
+			self.read += 1;
 			Some(byte)
 		} else {
 			self.index += 1;
@@ -199,8 +293,7 @@ impl<'a> Iterator for BlockPlacementIterator<'a> {
 				return None;
 			}
 
-			let block_pos = self.blocks[self.index] * self.block_size;
-			let block = &self.data[block_pos..block_pos + self.block_size];
+			let block = &self.data[self.blocks[self.index].clone()];
 			self.block_it = Some(BlockIterator::new(
 				Block(self.algorithm, block),
 				self.block_it.take(),
@@ -306,12 +399,13 @@ mod tests {
 
 		let embed_bits = BitVec::<u8>::from_slice(embed.as_slice());
 		let mut rand = ChaCha8Rng::from_seed([1u8; 32]);
-		let mut placement = BlockPlacement::new::<_>(
+		let order = block_order(data.len(), 4, &mut rand);
+		let blocks = block_spans(&order, 4, 0..data.len());
+		let mut placement = BlockPlacement::new(
 			&algorithm,
 			data.as_mut_slice(),
-			4,
 			embed_bits.len() / 8,
-			&mut rand,
+			blocks,
 		)
 		.unwrap();
 		placement.write_embed(embed_bits.as_bitslice());
@@ -336,6 +430,8 @@ mod tests {
 	}
 
 	#[test]
+	// Unfinished, the bytes read are not checked yet
+	#[allow(unused_variables)]
 	fn blockplacement_iterator() {
 		let algorithm = EmbedAlgorithm::Lo(4);
 		let data = vec![
@@ -345,7 +441,9 @@ mod tests {
 		];
 
 		let mut rand = ChaCha8Rng::from_seed([1u8; 32]);
-		let mut it = BlockPlacementIterator::new::<_>(&algorithm, &data, 4, &mut rand);
+		let order = block_order(data.len(), 4, &mut rand);
+		let mut it =
+			BlockPlacementIterator::new(&algorithm, &data, block_spans(&order, 4, 0..data.len()));
 
 		let mut rand = ChaCha8Rng::from_seed([1u8; 32]);
 		let mut positions = (0..8).collect::<Vec<_>>();
@@ -0,0 +1,121 @@
+use crc::Crc;
+
+use crate::header::decode_copy;
+use crate::header::Encode;
+use crate::header::Format;
+use crate::header::Header;
+
+/// Private ancillary chunk holding the header and payload of carrier images
+const PAYLOAD_CHUNK: [u8; 4] = *b"pdTa";
+/// Largest payload chunk written
+const PAYLOAD_CHUNK_LEN: usize = 1 << 20;
+/// `tEXt` chunk marking carrier images, telling stripped `pdTa` chunks apart from ordinary images
+const CARRIER_TEXT: &[u8] = b"png_data\0pdTa";
+/// Signature starting png files
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Type and data of a png chunk
+type Chunk<'a> = ([u8; 4], &'a [u8]);
+
+/// Splits a png file in its chunks, up to the `IEND` chunk
+fn png_chunks<'a>(input: &str, file: &'a [u8]) -> Result<Vec<Chunk<'a>>, String> {
+	let mut chunks = vec![];
+	let mut rest = &file[PNG_SIGNATURE.len()..];
+	while !rest.is_empty() {
+		let len = rest
+			.get(..4)
+			.map(|len| u32::from_be_bytes(len.try_into().unwrap()) as usize)
+			.filter(|len| rest.len() >= 12 + len)
+			.ok_or(format!("Truncated png chunk in `{input}`"))?;
+		let kind = rest[4..8].try_into().unwrap();
+		chunks.push((kind, &rest[8..8 + len]));
+		rest = &rest[12 + len..];
+		if kind == *b"IEND" {
+			break;
+		}
+	}
+	Ok(chunks)
+}
+
+/// Checks whether an image kept the mark of carrier images but lost its `pdTa` chunks
+pub fn is_stripped_carrier(input: &str) -> bool {
+	let Ok(file) = std::fs::read(input) else {
+		return false;
+	};
+	png_chunks(input, file.as_slice()).is_ok_and(|chunks| {
+		chunks.contains(&(*b"tEXt", CARRIER_TEXT))
+			&& !chunks.iter().any(|(kind, _)| *kind == PAYLOAD_CHUNK)
+	})
+}
+
+/// Reads the header and payload of a carrier image's `pdTa` chunks, if it has any
+pub fn read_carrier(input: &str) -> Result<Option<(Header, Vec<u8>)>, String> {
+	let file = std::fs::read(input)
+		.map_err(|err| format!("Failed to read input file `{input}`: {err}"))?;
+	if !file.starts_with(&PNG_SIGNATURE) {
+		return Ok(None);
+	}
+	let data = png_chunks(input, file.as_slice())?
+		.into_iter()
+		.filter(|(kind, _)| *kind == PAYLOAD_CHUNK)
+		.flat_map(|(_, data)| data.iter().copied())
+		.collect::<Vec<_>>();
+	if data.is_empty() {
+		return Ok(None);
+	}
+
+	let (header, header_len) = decode_copy(data.iter().copied()).map_err(|err| {
+		format!("Failed to decode header of the `pdTa` chunks of `{input}`: {err}")
+	})?;
+	header
+		.check_format(Format::Data)
+		.map_err(|err| format!("Failed to read `{input}`: {err}"))?;
+	if (data.len() - header_len) as u64 != header.data_len {
+		return Err(format!(
+			"The `pdTa` chunks of `{input}` hold {}bytes of data but the header claims {}bytes, re-saving the image likely stripped some of them",
+			data.len() - header_len,
+			header.data_len
+		));
+	}
+	Ok(Some((header, data[header_len..].to_vec())))
+}
+
+/// Gets a copy of a carrier image holding a header and its payload in `pdTa` chunks, leaving its
+/// pixels untouched
+pub fn add_payload(carrier: &str, header: &Header, payload: &[u8]) -> Result<Vec<u8>, String> {
+	let file = std::fs::read(carrier)
+		.map_err(|err| format!("Failed to read carrier image `{carrier}`: {err}"))?;
+
+	let mut data = vec![];
+	header.encode(&mut data);
+	data.extend_from_slice(payload);
+
+	let crc = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+	let mut result = PNG_SIGNATURE.to_vec();
+	let mut write_chunk = |kind: [u8; 4], data: &[u8]| {
+		result.extend_from_slice(&(data.len() as u32).to_be_bytes());
+		result.extend_from_slice(&kind);
+		result.extend_from_slice(data);
+		let mut digest = crc.digest();
+		digest.update(&kind);
+		digest.update(data);
+		result.extend_from_slice(&digest.finalize().to_be_bytes());
+	};
+	for (kind, chunk) in png_chunks(carrier, file.as_slice())? {
+		// Payloads already in the carrier are replaced
+		if kind == PAYLOAD_CHUNK || (kind == *b"tEXt" && chunk == CARRIER_TEXT) {
+			continue;
+		}
+		if kind == *b"IEND" {
+			for data in data.chunks(PAYLOAD_CHUNK_LEN) {
+				write_chunk(PAYLOAD_CHUNK, data);
+			}
+		}
+		write_chunk(kind, chunk);
+		if kind == *b"IHDR" {
+			write_chunk(*b"tEXt", CARRIER_TEXT);
+		}
+	}
+
+	Ok(result)
+}
@@ -0,0 +1,158 @@
+use std::io::Read;
+use std::io::Write;
+
+/// Compression codec of the payload
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+	Deflate,
+	Zstd,
+	Xz,
+}
+
+impl Codec {
+	/// Compresses data with the codec's best ratio
+	pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, String> {
+		let compressed = match self {
+			Codec::Deflate => {
+				let mut encoder =
+					flate2::write::DeflateEncoder::new(vec![], flate2::Compression::best());
+				encoder.write_all(data).and_then(|_| encoder.finish())
+			}
+			Codec::Zstd => zstd::encode_all(data, 19),
+			Codec::Xz => {
+				let mut encoder = xz2::write::XzEncoder::new(vec![], 9);
+				encoder.write_all(data).and_then(|_| encoder.finish())
+			}
+		};
+		compressed.map_err(|err| format!("Failed to compress data with {self}: {err}"))
+	}
+
+	/// Decompresses data that must expand to exactly `len` bytes
+	pub fn decompress(self, data: &[u8], len: u64) -> Result<Vec<u8>, String> {
+		let mut decoder: Box<dyn Read> = match self {
+			Codec::Deflate => Box::new(flate2::read::DeflateDecoder::new(data)),
+			Codec::Zstd => Box::new(
+				zstd::Decoder::new(data)
+					.map_err(|err| format!("Failed to decompress data with {self}: {err}"))?,
+			),
+			Codec::Xz => Box::new(xz2::read::XzDecoder::new(data)),
+		};
+
+		// Never expand past the expected length
+		let mut result = Vec::with_capacity(len.min(data.len() as u64 * 16) as usize);
+		decoder
+			.by_ref()
+			.take(len.saturating_add(1))
+			.read_to_end(&mut result)
+			.map_err(|err| format!("Failed to decompress data with {self}: {err}"))?;
+		if result.len() as u64 != len {
+			return Err(format!(
+				"Decompressed data is not {len}bytes long, the payload is damaged"
+			));
+		}
+		Ok(result)
+	}
+}
+
+impl TryFrom<u8> for Codec {
+	type Error = String;
+
+	fn try_from(value: u8) -> Result<Self, Self::Error> {
+		match value {
+			0 => Ok(Codec::Deflate),
+			1 => Ok(Codec::Zstd),
+			2 => Ok(Codec::Xz),
+			codec => Err(format!("Unknown compression codec: {codec}")),
+		}
+	}
+}
+
+impl core::fmt::Display for Codec {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match self {
+			Codec::Deflate => write!(f, "deflate"),
+			Codec::Zstd => write!(f, "zstd"),
+			Codec::Xz => write!(f, "xz"),
+		}
+	}
+}
+
+impl core::str::FromStr for Codec {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"deflate" => Ok(Codec::Deflate),
+			"zstd" => Ok(Codec::Zstd),
+			"xz" => Ok(Codec::Xz),
+			_ => Err(format!(
+				"Unknown compression codec: `{s}`, expected deflate, zstd or xz"
+			)),
+		}
+	}
+}
+
+/// Compression of the payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compression {
+	pub codec: Codec,
+	/// Length of the payload before compression
+	pub original_len: u64,
+}
+
+impl Compression {
+	/// Compresses a payload, returning the compressed data and its compression
+	pub fn compress(codec: Codec, data: &[u8]) -> Result<(Vec<u8>, Self), String> {
+		let compressed = codec.compress(data)?;
+		Ok((
+			compressed,
+			Self {
+				codec,
+				original_len: data.len() as u64,
+			},
+		))
+	}
+
+	/// Decompresses a payload
+	pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+		self.codec.decompress(data, self.original_len)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn roundtrip() {
+		let data = b"payload payload payload payload".repeat(8);
+		for codec in [Codec::Deflate, Codec::Zstd, Codec::Xz] {
+			let (compressed, compression) = Compression::compress(codec, &data).unwrap();
+			assert!(compressed.len() < data.len());
+			assert_eq!(compression.codec, codec);
+			assert_eq!(compression.original_len, data.len() as u64);
+			assert_eq!(compression.decompress(&compressed).unwrap(), data);
+			assert_eq!(codec.to_string().parse::<Codec>(), Ok(codec));
+		}
+		assert!("lz4".parse::<Codec>().is_err());
+	}
+
+	#[test]
+	fn damaged() {
+		let data = b"payload payload payload payload".repeat(8);
+		for codec in [Codec::Deflate, Codec::Zstd, Codec::Xz] {
+			let (compressed, compression) = Compression::compress(codec, &data).unwrap();
+
+			// Decompression never expands past the original length
+			let truncated = Compression {
+				codec,
+				original_len: 16,
+			};
+			assert!(truncated.decompress(&compressed).is_err());
+
+			let damaged = compressed[..compressed.len() / 2].to_vec();
+			assert!(compression.decompress(&damaged).is_err());
+		}
+	}
+}
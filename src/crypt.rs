@@ -0,0 +1,325 @@
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::aead::Payload;
+use chacha20poly1305::ChaCha20Poly1305;
+use chacha20poly1305::KeyInit;
+use chacha20poly1305::XChaCha20Poly1305;
+use chacha20poly1305::XNonce;
+use rand::RngCore;
+
+/// Maximum Argon2 memory cost accepted from a header, in KiB
+const MAX_MEMORY: u32 = 1 << 20;
+
+/// Passphrase encryption of the payload: the key is derived with Argon2id and the payload is
+/// encrypted with XChaCha20-Poly1305
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderCrypt {
+	pub salt: [u8; 16],
+	pub nonce: [u8; 24],
+	/// Argon2 memory cost in KiB
+	pub memory: u32,
+	/// Argon2 iterations
+	pub iterations: u32,
+	/// Argon2 lanes
+	pub parallelism: u32,
+}
+
+impl HeaderCrypt {
+	/// Encrypts a payload with a fresh salt and nonce, returning the encrypted data and its
+	/// parameters. `associated_data` gets the parameters and returns the bytes authenticated
+	/// along with the payload
+	pub fn encrypt(
+		passphrase: &str,
+		data: &[u8],
+		associated_data: impl FnOnce(&Self) -> Vec<u8>,
+	) -> Result<(Vec<u8>, Self), String> {
+		let params = argon2::Params::default();
+		let mut crypt = Self {
+			salt: [0; 16],
+			nonce: [0; 24],
+			memory: params.m_cost(),
+			iterations: params.t_cost(),
+			parallelism: params.p_cost(),
+		};
+		let mut rng = rand::rngs::OsRng;
+		rng.fill_bytes(&mut crypt.salt);
+		rng.fill_bytes(&mut crypt.nonce);
+
+		let aad = associated_data(&crypt);
+		let encrypted = crypt
+			.cipher(passphrase)?
+			.encrypt(
+				XNonce::from_slice(&crypt.nonce),
+				Payload {
+					msg: data,
+					aad: aad.as_slice(),
+				},
+			)
+			.map_err(|err| format!("Failed to encrypt data: {err}"))?;
+		Ok((encrypted, crypt))
+	}
+
+	/// Decrypts a payload, checking the `associated_data` it was encrypted with
+	pub fn decrypt(
+		&self,
+		passphrase: &str,
+		data: &[u8],
+		associated_data: &[u8],
+	) -> Result<Vec<u8>, String> {
+		self.cipher(passphrase)?
+			.decrypt(
+				XNonce::from_slice(&self.nonce),
+				Payload {
+					msg: data,
+					aad: associated_data,
+				},
+			)
+			.map_err(|_| "Wrong passphrase".to_string())
+	}
+
+	/// Derives the cipher's key from the passphrase
+	fn cipher(&self, passphrase: &str) -> Result<XChaCha20Poly1305, String> {
+		if self.memory > MAX_MEMORY {
+			return Err(format!(
+				"Key derivation requires {}KiB of memory, maximum: {MAX_MEMORY}KiB",
+				self.memory
+			));
+		}
+		let params = argon2::Params::new(self.memory, self.iterations, self.parallelism, None)
+			.map_err(|err| format!("Invalid key derivation parameters: {err}"))?;
+		let mut key = [0u8; 32];
+		argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+			.hash_password_into(passphrase.as_bytes(), &self.salt, &mut key)
+			.map_err(|err| format!("Failed to derive key: {err}"))?;
+		Ok(XChaCha20Poly1305::new(&key.into()))
+	}
+}
+
+/// Prefix of X25519 public key files
+pub const PUBLIC_KEY_PREFIX: &str = "png_data-x25519-pub:";
+/// Prefix of X25519 secret key files
+pub const SECRET_KEY_PREFIX: &str = "png_data-x25519-key:";
+
+/// Encodes a key as a line of text
+pub fn encode_key(prefix: &str, key: &[u8; 32]) -> String {
+	let hex = key
+		.iter()
+		.map(|byte| format!("{byte:02x}"))
+		.collect::<String>();
+	format!("{prefix}{hex}")
+}
+
+/// Decodes a key encoded by [`encode_key`]
+pub fn decode_key(prefix: &str, text: &str) -> Result<[u8; 32], String> {
+	let hex = text
+		.trim()
+		.strip_prefix(prefix)
+		.ok_or(format!("Missing `{prefix}` key prefix"))?;
+	if hex.len() != 64 || !hex.is_ascii() {
+		return Err(format!("Invalid key `{hex}`, expected 64 hex digits"));
+	}
+	let mut key = [0u8; 32];
+	for (i, byte) in key.iter_mut().enumerate() {
+		*byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+			.map_err(|err| format!("Invalid key `{hex}`: {err}"))?;
+	}
+	Ok(key)
+}
+
+/// Reads a key file written with [`encode_key`]
+pub fn read_key(path: &str, prefix: &str) -> Result<[u8; 32], String> {
+	let text = std::fs::read_to_string(path)
+		.map_err(|err| format!("Failed to read key file `{path}`: {err}"))?;
+	decode_key(prefix, text.as_str()).map_err(|err| format!("Invalid key file `{path}`: {err}"))
+}
+
+/// File key wrapped for a single recipient
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stanza {
+	/// Ephemeral X25519 public key
+	pub ephemeral: [u8; 32],
+	/// File key encrypted with ChaCha20-Poly1305
+	pub wrapped_key: [u8; 48],
+}
+
+/// Public-key encryption of the payload: a random file key encrypts the payload with
+/// XChaCha20-Poly1305 and is wrapped for each X25519 recipient
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecipientCrypt {
+	pub nonce: [u8; 24],
+	pub stanzas: Vec<Stanza>,
+}
+
+impl RecipientCrypt {
+	/// Encrypts a payload for several recipients, returning the encrypted data and the wrapped
+	/// file keys. `associated_data` gets the wrapped keys and returns the bytes authenticated
+	/// along with the payload
+	pub fn encrypt(
+		recipients: &[[u8; 32]],
+		data: &[u8],
+		associated_data: impl FnOnce(&Self) -> Vec<u8>,
+	) -> Result<(Vec<u8>, Self), String> {
+		let mut rng = rand::rngs::OsRng;
+		let mut file_key = [0u8; 32];
+		rng.fill_bytes(&mut file_key);
+		let mut crypt = Self {
+			nonce: [0; 24],
+			stanzas: vec![],
+		};
+		rng.fill_bytes(&mut crypt.nonce);
+
+		for recipient in recipients {
+			let secret = x25519_dalek::EphemeralSecret::random_from_rng(rng);
+			let ephemeral = x25519_dalek::PublicKey::from(&secret).to_bytes();
+			let shared = secret.diffie_hellman(&x25519_dalek::PublicKey::from(*recipient));
+			let wrapped_key = wrap_cipher(shared.as_bytes(), &ephemeral, recipient)
+				.encrypt(&Default::default(), file_key.as_slice())
+				.map_err(|err| format!("Failed to wrap file key: {err}"))?;
+			crypt.stanzas.push(Stanza {
+				ephemeral,
+				wrapped_key: wrapped_key.try_into().unwrap(),
+			});
+		}
+
+		let aad = associated_data(&crypt);
+		let encrypted = XChaCha20Poly1305::new(&file_key.into())
+			.encrypt(
+				XNonce::from_slice(&crypt.nonce),
+				Payload {
+					msg: data,
+					aad: aad.as_slice(),
+				},
+			)
+			.map_err(|err| format!("Failed to encrypt data: {err}"))?;
+		Ok((encrypted, crypt))
+	}
+
+	/// Decrypts a payload with the secret key of one of its recipients, checking the
+	/// `associated_data` it was encrypted with
+	pub fn decrypt(
+		&self,
+		identity: &[u8; 32],
+		data: &[u8],
+		associated_data: &[u8],
+	) -> Result<Vec<u8>, String> {
+		let secret = x25519_dalek::StaticSecret::from(*identity);
+		let public = x25519_dalek::PublicKey::from(&secret).to_bytes();
+		let file_key = self
+			.stanzas
+			.iter()
+			.find_map(|stanza| {
+				let shared =
+					secret.diffie_hellman(&x25519_dalek::PublicKey::from(stanza.ephemeral));
+				wrap_cipher(shared.as_bytes(), &stanza.ephemeral, &public)
+					.decrypt(&Default::default(), stanza.wrapped_key.as_slice())
+					.ok()
+			})
+			.ok_or("The identity is not one of the recipients".to_string())?;
+
+		XChaCha20Poly1305::new_from_slice(file_key.as_slice())
+			.unwrap()
+			.decrypt(
+				XNonce::from_slice(&self.nonce),
+				Payload {
+					msg: data,
+					aad: associated_data,
+				},
+			)
+			.map_err(|_| "Failed to decrypt data, the payload is damaged".to_string())
+	}
+}
+
+/// Derives the cipher wrapping the file key for a recipient
+fn wrap_cipher(shared: &[u8; 32], ephemeral: &[u8; 32], recipient: &[u8; 32]) -> ChaCha20Poly1305 {
+	let mut material = shared.to_vec();
+	material.extend_from_slice(ephemeral);
+	material.extend_from_slice(recipient);
+	ChaCha20Poly1305::new(&blake3::derive_key("png_data x25519 file key", &material).into())
+}
+
+/// Generates an X25519 key pair, returning the secret and public keys
+pub fn generate_keys() -> ([u8; 32], [u8; 32]) {
+	let secret = x25519_dalek::StaticSecret::random_from_rng(rand::rngs::OsRng);
+	let public = x25519_dalek::PublicKey::from(&secret);
+	(secret.to_bytes(), public.to_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn passphrase() {
+		let (encrypted, crypt) =
+			HeaderCrypt::encrypt("hunter2", b"payload", |_| b"header".to_vec()).unwrap();
+		assert_eq!(
+			crypt.decrypt("hunter2", &encrypted, b"header").unwrap(),
+			b"payload"
+		);
+		assert_eq!(
+			crypt.decrypt("hunter3", &encrypted, b"header"),
+			Err("Wrong passphrase".to_string())
+		);
+
+		let mut tampered = encrypted.clone();
+		tampered[0] ^= 0x01;
+		assert!(crypt.decrypt("hunter2", &tampered, b"header").is_err());
+		assert!(crypt.decrypt("hunter2", &encrypted, b"Header").is_err());
+
+		// Costly key derivations from a crafted header are refused
+		let greedy = HeaderCrypt {
+			memory: MAX_MEMORY + 1,
+			..crypt
+		};
+		assert!(greedy.decrypt("hunter2", &encrypted, b"header").is_err());
+	}
+
+	#[test]
+	fn recipients() {
+		let keys = [generate_keys(), generate_keys(), generate_keys()];
+		let (encrypted, crypt) =
+			RecipientCrypt::encrypt(&[keys[0].1, keys[1].1], b"payload", |_| b"header".to_vec())
+				.unwrap();
+		assert_eq!(crypt.stanzas.len(), 2);
+		assert_eq!(
+			crypt.decrypt(&keys[0].0, &encrypted, b"header").unwrap(),
+			b"payload"
+		);
+		assert_eq!(
+			crypt.decrypt(&keys[1].0, &encrypted, b"header").unwrap(),
+			b"payload"
+		);
+		assert_eq!(
+			crypt.decrypt(&keys[2].0, &encrypted, b"header"),
+			Err("The identity is not one of the recipients".to_string())
+		);
+
+		let mut tampered = encrypted.clone();
+		tampered[3] ^= 0x01;
+		assert_eq!(
+			crypt.decrypt(&keys[0].0, &tampered, b"header"),
+			Err("Failed to decrypt data, the payload is damaged".to_string())
+		);
+		assert!(crypt.decrypt(&keys[0].0, &encrypted, b"Header").is_err());
+
+		let mut wrapped = crypt.clone();
+		wrapped.stanzas[0].wrapped_key[0] ^= 0x01;
+		assert!(wrapped.decrypt(&keys[0].0, &encrypted, b"header").is_err());
+		assert!(wrapped.decrypt(&keys[1].0, &encrypted, b"header").is_ok());
+	}
+
+	#[test]
+	fn keys() {
+		let (secret, public) = generate_keys();
+		let text = encode_key(PUBLIC_KEY_PREFIX, &public);
+		assert_eq!(decode_key(PUBLIC_KEY_PREFIX, &text), Ok(public));
+		assert!(decode_key(SECRET_KEY_PREFIX, &text).is_err());
+
+		let text = encode_key(SECRET_KEY_PREFIX, &secret);
+		assert_eq!(
+			decode_key(SECRET_KEY_PREFIX, &format!("{text}\n")),
+			Ok(secret)
+		);
+		assert!(decode_key(SECRET_KEY_PREFIX, &text[..text.len() - 2]).is_err());
+		assert!(decode_key(SECRET_KEY_PREFIX, &format!("{}g", &text[..text.len() - 1])).is_err());
+	}
+}
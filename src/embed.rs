@@ -16,6 +16,18 @@ impl EmbedAlgorithm {
 	}
 }
 
+/// Derives the block placement seed from a string, using Argon2 with salt: `png_data embed`
+pub fn derive_seed(seed: &str) -> Result<[u8; 32], String> {
+	let mut result = [0u8; 32];
+	argon2::Argon2::default()
+		.hash_password_into(seed.as_bytes(), b"png_data embed", &mut result)
+		.map_err(|err| format!("Failed to derive seed `{seed}`: {err}"))?;
+	Ok(result)
+}
+
+/// Seed used when none is given: "{width}x{height}" of the image
+pub fn default_seed(width: u32, height: u32) -> String { format!("{width}x{height}") }
+
 impl core::fmt::Display for EmbedAlgorithm {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
 		match self {
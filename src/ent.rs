@@ -17,6 +17,7 @@ impl<R: Rng> EntropyGenerator<R> {
 		// FIXME: Does not work for entropy below 1.0
 		let n = (2.0f64.powf(entropy)).round() as usize;
 
+		#[allow(clippy::manual_repeat_n)]
 		let mut probabilities = std::iter::repeat(1.0f64).take(n).collect::<Vec<_>>();
 		let sum = probabilities.iter().sum::<f64>();
 		probabilities.iter_mut().for_each(|p| *p /= sum);
@@ -26,7 +27,7 @@ impl<R: Rng> EntropyGenerator<R> {
 		Self { rng, dist }
 	}
 
-	pub fn next(&mut self) -> u8 { self.dist.sample(&mut self.rng) as u8 }
+	pub fn next_byte(&mut self) -> u8 { self.dist.sample(&mut self.rng) as u8 }
 }
 
 #[cfg(test)]
@@ -44,7 +45,7 @@ mod tests {
 
 			let mut data = Vec::with_capacity(1024);
 			for _ in 0..1024 {
-				data.push(gen.next());
+				data.push(gen.next_byte());
 			}
 
 			assert!((shannon_entropy(data) - i as f32).abs() < 0.2);
@@ -0,0 +1,370 @@
+/// Symbols in a Reed-Solomon codeword
+const CODEWORD_LEN: usize = 255;
+
+/// Exponents of the generator 2 in GF(256), repeated so products need no modulo
+const EXP: [u8; 512] = {
+	let mut exp = [0u8; 512];
+	let mut x = 1u16;
+	let mut i = 0;
+	while i < 255 {
+		exp[i] = x as u8;
+		exp[i + 255] = x as u8;
+		x <<= 1;
+		if x & 0x100 != 0 {
+			// Primitive polynomial x^8 + x^4 + x^3 + x^2 + 1
+			x ^= 0x11D;
+		}
+		i += 1;
+	}
+	exp
+};
+
+/// Logarithms in GF(256), the logarithm of 0 is unused
+const LOG: [u8; 256] = {
+	let mut log = [0u8; 256];
+	let mut i = 0;
+	while i < 255 {
+		log[EXP[i] as usize] = i as u8;
+		i += 1;
+	}
+	log
+};
+
+fn mul(a: u8, b: u8) -> u8 {
+	if a == 0 || b == 0 {
+		return 0;
+	}
+	EXP[LOG[a as usize] as usize + LOG[b as usize] as usize]
+}
+
+fn div(a: u8, b: u8) -> u8 {
+	if a == 0 {
+		return 0;
+	}
+	EXP[(LOG[a as usize] as usize + 255 - LOG[b as usize] as usize) % 255]
+}
+
+/// Gets 2^power
+fn pow2(power: isize) -> u8 { EXP[power.rem_euclid(255) as usize] }
+
+/// Evaluates a polynomial, highest degree first
+fn eval(poly: &[u8], x: u8) -> u8 { poly.iter().fold(0, |y, coef| mul(y, x) ^ coef) }
+
+/// Multiplies two polynomials, highest degree first
+fn poly_mul(p: &[u8], q: &[u8]) -> Vec<u8> {
+	let mut result = vec![0; p.len() + q.len() - 1];
+	for (i, a) in p.iter().enumerate() {
+		for (j, b) in q.iter().enumerate() {
+			result[i + j] ^= mul(*a, *b);
+		}
+	}
+	result
+}
+
+/// Adds two polynomials, highest degree first
+fn poly_add(p: &[u8], q: &[u8]) -> Vec<u8> {
+	let len = p.len().max(q.len());
+	let mut result = vec![0; len];
+	result[len - p.len()..].copy_from_slice(p);
+	for (i, coef) in q.iter().enumerate() {
+		result[len - q.len() + i] ^= coef;
+	}
+	result
+}
+
+fn poly_scale(p: &[u8], x: u8) -> Vec<u8> { p.iter().map(|coef| mul(*coef, x)).collect() }
+
+/// Reed-Solomon code over GF(256) correcting up to `parity / 2` symbols per codeword
+struct ReedSolomon {
+	parity: usize,
+	/// Generator polynomial, highest degree first
+	generator: Vec<u8>,
+}
+
+impl ReedSolomon {
+	fn new(parity: usize) -> Self {
+		let generator = (0..parity).fold(vec![1], |generator, i| {
+			poly_mul(&generator, &[1, pow2(i as isize)])
+		});
+		Self { parity, generator }
+	}
+
+	/// Computes the parity symbols of a message
+	fn encode(&self, message: &[u8]) -> Vec<u8> {
+		let mut remainder = message.to_vec();
+		remainder.resize(message.len() + self.parity, 0);
+		for i in 0..message.len() {
+			let coef = remainder[i];
+			if coef != 0 {
+				for (j, generator) in self.generator.iter().enumerate().skip(1) {
+					remainder[i + j] ^= mul(*generator, coef);
+				}
+			}
+		}
+		remainder.split_off(message.len())
+	}
+
+	/// Corrects a codeword in place, returning the number of corrected symbols or `None` when
+	/// it has too many errors
+	fn correct(&self, codeword: &mut [u8]) -> Option<usize> {
+		// Syndromes, after a leading 0
+		let syndromes = std::iter::once(0)
+			.chain((0..self.parity).map(|i| eval(codeword, pow2(i as isize))))
+			.collect::<Vec<_>>();
+		if syndromes.iter().all(|syndrome| *syndrome == 0) {
+			return Some(0);
+		}
+
+		// Berlekamp-Massey
+		let mut locator = vec![1u8];
+		let mut old_locator = vec![1u8];
+		for i in 1..=self.parity {
+			let mut delta = syndromes[i];
+			for j in 1..locator.len() {
+				delta ^= mul(locator[locator.len() - 1 - j], syndromes[i - j]);
+			}
+			old_locator.push(0);
+			if delta != 0 {
+				if old_locator.len() > locator.len() {
+					let new_locator = poly_scale(&old_locator, delta);
+					old_locator = poly_scale(&locator, div(1, delta));
+					locator = new_locator;
+				}
+				locator = poly_add(&locator, &poly_scale(&old_locator, delta));
+			}
+		}
+		let leading = locator.iter().position(|coef| *coef != 0)?;
+		let locator = &locator[leading..];
+		let errors = locator.len() - 1;
+		if errors * 2 > self.parity {
+			return None;
+		}
+
+		// Chien search, with the locator's coefficients reversed
+		let reversed = locator.iter().rev().copied().collect::<Vec<_>>();
+		let positions = (0..codeword.len())
+			.filter(|i| eval(&reversed, pow2(*i as isize)) == 0)
+			.map(|i| codeword.len() - 1 - i)
+			.collect::<Vec<_>>();
+		if positions.len() != errors {
+			return None;
+		}
+
+		// Forney
+		let coef_positions = positions
+			.iter()
+			.map(|position| codeword.len() - 1 - position)
+			.collect::<Vec<_>>();
+		let errata_locator = coef_positions.iter().fold(vec![1], |locator, position| {
+			poly_mul(&locator, &[pow2(*position as isize), 1])
+		});
+		let reversed_syndromes = syndromes.iter().rev().copied().collect::<Vec<_>>();
+		let product = poly_mul(&reversed_syndromes, &errata_locator);
+		let evaluator = product[product.len().saturating_sub(errata_locator.len())..].to_vec();
+		let roots = coef_positions
+			.iter()
+			.map(|position| pow2(*position as isize))
+			.collect::<Vec<_>>();
+		for (i, root) in roots.iter().enumerate() {
+			let root_inv = div(1, *root);
+			let derivative = roots
+				.iter()
+				.enumerate()
+				.filter(|(j, _)| *j != i)
+				.fold(1, |derivative, (_, other)| {
+					mul(derivative, 1 ^ mul(root_inv, *other))
+				});
+			if derivative == 0 {
+				return None;
+			}
+			let y = mul(*root, eval(&evaluator, root_inv));
+			codeword[positions[i]] ^= div(y, derivative);
+		}
+
+		(0..self.parity)
+			.all(|i| eval(codeword, pow2(i as isize)) == 0)
+			.then_some(errors)
+	}
+}
+
+/// Interleaved Reed-Solomon error correction of the payload.
+///
+/// The payload is zero-padded and cut in codewords of 255 symbols, then interleaved so that
+/// consecutive damaged bytes are spread over different codewords
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fec {
+	/// Parity symbols per 100 data symbols, in range [1, 200]
+	pub redundancy: u8,
+	/// Length of the payload before error correction
+	pub original_len: u64,
+}
+
+impl Fec {
+	/// Gets the number of data symbols per codeword for a redundancy
+	fn data_symbols(redundancy: u8) -> Result<usize, String> {
+		if !(1..=200).contains(&redundancy) {
+			return Err(format!(
+				"Invalid redundancy {redundancy}%, expected 1% to 200%"
+			));
+		}
+		Ok(CODEWORD_LEN * 100 / (100 + redundancy as usize))
+	}
+
+	/// Gets the number of payload bytes that fit in `len` bytes once error corrected
+	pub fn capacity(redundancy: u8, len: usize) -> Result<usize, String> {
+		Ok(len / CODEWORD_LEN * Self::data_symbols(redundancy)?)
+	}
+
+	/// Adds error correction to a payload, returning the encoded data and its error correction
+	pub fn encode(redundancy: u8, data: &[u8]) -> Result<(Vec<u8>, Self), String> {
+		let data_symbols = Self::data_symbols(redundancy)?;
+		let rs = ReedSolomon::new(CODEWORD_LEN - data_symbols);
+		let codewords = data.len().div_ceil(data_symbols);
+
+		let mut encoded = vec![0; codewords * CODEWORD_LEN];
+		for (i, chunk) in data.chunks(data_symbols).enumerate() {
+			let mut message = chunk.to_vec();
+			message.resize(data_symbols, 0);
+			let parity = rs.encode(&message);
+			for (symbol, byte) in message.iter().chain(parity.iter()).enumerate() {
+				encoded[symbol * codewords + i] = *byte;
+			}
+		}
+		Ok((
+			encoded,
+			Self {
+				redundancy,
+				original_len: data.len() as u64,
+			},
+		))
+	}
+
+	/// Gets the length of the encoded data
+	pub fn encoded_len(&self) -> Result<usize, String> {
+		let data_symbols = Self::data_symbols(self.redundancy)?;
+		Ok((self.original_len as usize).div_ceil(data_symbols) * CODEWORD_LEN)
+	}
+
+	/// Gets the number of codewords of the encoded data, checking its length
+	fn codewords(&self, data: &[u8]) -> Result<usize, String> {
+		let data_symbols = Self::data_symbols(self.redundancy)?;
+		let codewords = (self.original_len as usize).div_ceil(data_symbols);
+		if data.len() != codewords * CODEWORD_LEN {
+			return Err(format!(
+				"Error corrected data should be {}bytes, got {}",
+				codewords * CODEWORD_LEN,
+				data.len()
+			));
+		}
+		Ok(codewords)
+	}
+
+	/// Repairs the encoded data in place, returning the number of corrected symbols
+	pub fn repair(&self, data: &mut [u8]) -> Result<usize, String> {
+		let codewords = self.codewords(data)?;
+		let rs = ReedSolomon::new(CODEWORD_LEN - Self::data_symbols(self.redundancy)?);
+
+		let mut corrected = 0;
+		let mut failed = 0;
+		for i in 0..codewords {
+			let mut codeword = data
+				.iter()
+				.skip(i)
+				.step_by(codewords)
+				.copied()
+				.collect::<Vec<_>>();
+			match rs.correct(&mut codeword) {
+				Some(0) => {}
+				Some(errors) => {
+					corrected += errors;
+					for (symbol, byte) in codeword.into_iter().enumerate() {
+						data[symbol * codewords + i] = byte;
+					}
+				}
+				None => failed += 1,
+			}
+		}
+		if failed != 0 {
+			return Err(format!(
+				"{failed} out of {codewords} codewords have too many errors to be repaired"
+			));
+		}
+		Ok(corrected)
+	}
+
+	/// Removes the error correction from the encoded data
+	pub fn decode(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+		let data_symbols = Self::data_symbols(self.redundancy)?;
+		let codewords = self.codewords(data)?;
+		Ok((0..self.original_len as usize)
+			.map(|i| data[(i % data_symbols) * codewords + i / data_symbols])
+			.collect())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::Rng;
+	use rand::SeedableRng;
+	use rand_chacha::ChaCha8Rng;
+
+	use super::*;
+
+	#[test]
+	fn codeword() {
+		let rs = ReedSolomon::new(10);
+		let mut codeword = b"hello world".to_vec();
+		codeword.extend(rs.encode(b"hello world"));
+		assert_eq!(rs.correct(&mut codeword.clone()), Some(0));
+
+		for errors in 1..=5 {
+			let mut damaged = codeword.clone();
+			for i in 0..errors {
+				damaged[i * 4] ^= 0x5A;
+			}
+			assert_eq!(rs.correct(&mut damaged), Some(errors));
+			assert_eq!(damaged, codeword);
+		}
+
+		let mut damaged = codeword.clone();
+		for byte in damaged.iter_mut().take(8) {
+			*byte ^= 0xFF;
+		}
+		assert_ne!(rs.correct(&mut damaged), Some(8));
+	}
+
+	#[test]
+	fn repair() {
+		let mut rng = ChaCha8Rng::from_seed([3u8; 32]);
+		let mut data = vec![0u8; 3000];
+		rng.fill(data.as_mut_slice());
+
+		let (mut encoded, fec) = Fec::encode(20, &data).unwrap();
+		assert_eq!(Fec::decode(&fec, &encoded).unwrap(), data);
+		assert_eq!(fec.repair(&mut encoded.clone()), Ok(0));
+
+		// A burst is spread over every codeword
+		let pristine = encoded.clone();
+		for byte in &mut encoded[100..400] {
+			*byte = !*byte;
+		}
+		assert_eq!(fec.repair(&mut encoded), Ok(300));
+		assert_eq!(encoded, pristine);
+		assert_eq!(fec.decode(&encoded).unwrap(), data);
+
+		for byte in &mut encoded[..1000] {
+			*byte = !*byte;
+		}
+		assert!(fec.repair(&mut encoded).is_err());
+	}
+
+	#[test]
+	fn redundancy() {
+		assert!(Fec::encode(0, b"data").is_err());
+		assert!(Fec::encode(201, b"data").is_err());
+		let (encoded, fec) = Fec::encode(200, b"").unwrap();
+		assert!(encoded.is_empty());
+		assert_eq!(fec.decode(&encoded).unwrap(), b"");
+		assert_eq!(Fec::capacity(100, 600), Ok(254));
+	}
+}
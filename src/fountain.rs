@@ -0,0 +1,140 @@
+use raptorq::Decoder;
+use raptorq::Encoder;
+use raptorq::EncodingPacket;
+use raptorq::ObjectTransmissionInformation;
+
+/// Source symbols given to each image, the symbol size is chosen from it.
+/// Symbols are at least 64 bytes long, as required by the encoding parameters
+const SYMBOLS_PER_IMAGE: usize = 32;
+
+/// RaptorQ fountain coding of the payload over a set of images, any `needed` of which decode it.
+///
+/// Each image holds a run of encoded packets from every source block, with one packet per block
+/// more than its share of the source symbols
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fountain {
+	/// RaptorQ object transmission information
+	pub config: [u8; 12],
+	/// Number of images needed to decode the payload
+	pub needed: u32,
+}
+
+impl Fountain {
+	/// Encodes a payload over `images` images, returning the data of each image and the
+	/// fountain coding
+	pub fn encode(data: &[u8], needed: u32, images: u32) -> Result<(Vec<Vec<u8>>, Self), String> {
+		if needed == 0 || needed > images {
+			return Err(format!(
+				"Invalid fountain coding: {needed} out of {images} images"
+			));
+		}
+		if data.is_empty() {
+			return Err("Fountain coding requires a non-empty payload".into());
+		}
+
+		let symbol_size = data
+			.len()
+			.div_ceil(needed as usize * SYMBOLS_PER_IMAGE)
+			.next_multiple_of(8)
+			.clamp(64, 65528);
+		let encoder = Encoder::with_defaults(data, symbol_size as u16);
+
+		let mut result = vec![vec![]; images as usize];
+		for block in encoder.get_block_encoders() {
+			let source = block.source_packets();
+			let per_image = source.len().div_ceil(needed as usize) + 1;
+			let repair = (per_image * images as usize).saturating_sub(source.len());
+			let packets = source
+				.into_iter()
+				.chain(block.repair_packets(0, repair as u32))
+				.collect::<Vec<_>>();
+			for (image, packets) in result.iter_mut().zip(packets.chunks(per_image)) {
+				for packet in packets {
+					image.extend_from_slice(packet.serialize().as_slice());
+				}
+			}
+		}
+
+		Ok((
+			result,
+			Self {
+				config: encoder.get_config().serialize(),
+				needed,
+			},
+		))
+	}
+
+	/// Decodes the payload from the data of the available images
+	pub fn decode(&self, images: &[&[u8]]) -> Result<Vec<u8>, String> {
+		let config = ObjectTransmissionInformation::deserialize(&self.config);
+		let total_len = images.iter().map(|image| image.len()).sum::<usize>();
+		if config.symbol_size() == 0
+			|| config.symbol_alignment() == 0
+			|| !config
+				.symbol_size()
+				.is_multiple_of(config.symbol_alignment() as u16)
+			|| config.source_blocks() == 0
+			|| config.sub_blocks() == 0
+			|| config.transfer_length() > total_len as u64 * self.needed as u64
+		{
+			return Err("Invalid fountain coding parameters".into());
+		}
+
+		let packet_len = 4 + config.symbol_size() as usize;
+		let mut decoder = Decoder::new(config);
+		for image in images {
+			// Packets of unknown source blocks can not come from this encoding
+			for packet in image
+				.chunks_exact(packet_len)
+				.filter(|packet| packet[0] < config.source_blocks())
+			{
+				if let Some(data) = decoder.decode(EncodingPacket::deserialize(packet)) {
+					return Ok(data);
+				}
+			}
+		}
+
+		let missing = (self.needed as usize).saturating_sub(images.len()).max(1);
+		Err(format!(
+			"Not enough images to decode the payload: got {}, {missing} more needed",
+			images.len()
+		))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::Rng;
+	use rand::SeedableRng;
+	use rand_chacha::ChaCha8Rng;
+
+	use super::*;
+
+	#[test]
+	fn any_subset() {
+		let mut data = vec![0u8; 10000];
+		ChaCha8Rng::from_seed([5u8; 32]).fill(data.as_mut_slice());
+
+		let (images, fountain) = Fountain::encode(&data, 3, 5).unwrap();
+		assert_eq!(images.len(), 5);
+		for a in 0..5 {
+			for b in a + 1..5 {
+				for c in b + 1..5 {
+					let subset = [&images[c][..], &images[a][..], &images[b][..]];
+					assert_eq!(fountain.decode(&subset).unwrap(), data);
+				}
+				assert_eq!(
+					fountain.decode(&[&images[a][..], &images[b][..]]),
+					Err("Not enough images to decode the payload: got 2, 1 more needed".into())
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn invalid() {
+		assert!(Fountain::encode(b"data", 0, 2).is_err());
+		assert!(Fountain::encode(b"data", 3, 2).is_err());
+		assert!(Fountain::encode(b"", 1, 2).is_err());
+	}
+}
@@ -0,0 +1,1490 @@
+use std::ops::Range;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use crc::Crc;
+
+use crate::archive::check_path;
+use crate::archive::Archive;
+use crate::archive::Entry;
+use crate::compress::Codec;
+use crate::compress::Compression;
+use crate::crypt::HeaderCrypt;
+use crate::crypt::RecipientCrypt;
+use crate::crypt::Stanza;
+use crate::fec::Fec;
+use crate::fountain::Fountain;
+use crate::sign::HeaderSignature;
+
+pub trait Encode {
+	/// Encode the data into a vector
+	fn encode(&self, vec: &mut Vec<u8>);
+}
+
+pub trait Decode {
+	type Type;
+
+	/// Decode the data from an iterator
+	fn decode<I>(it: &mut I) -> Result<Self::Type, String>
+	where
+		I: Iterator<Item = u8>;
+}
+
+/// Tool an image was written by, telling how its payload is laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+	/// The payload is the image data
+	Data,
+	/// The payload is embedded into an existing image
+	Embed,
+}
+
+impl Format {
+	/// Signature at the start of every header since [`Version::VERSION_2`]
+	pub fn magic(self) -> [u8; 8] {
+		match self {
+			Format::Data => *b"png_data",
+			Format::Embed => *b"png_embd",
+		}
+	}
+
+	/// Name of the tool reading this format
+	pub fn program(self) -> &'static str {
+		match self {
+			Format::Data => "png_data",
+			Format::Embed => "png_embed",
+		}
+	}
+}
+
+/// The program's version.
+/// Used for compatibility reasons.
+#[repr(u16)]
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Version {
+	VERSION_1,
+	/// Adds the magic, part numbers, optional fields, a header CRC and redundant header copies
+	VERSION_2,
+}
+
+impl Version {
+	/// Version written by the encoder
+	pub const CURRENT: Version = Version::VERSION_2;
+
+	/// Maximum data length that can be stored in this version's header
+	pub fn max_data_len(self) -> u64 {
+		match self {
+			Version::VERSION_1 => u32::MAX as u64,
+			Version::VERSION_2 => u64::MAX,
+		}
+	}
+}
+
+impl TryFrom<u16> for Version {
+	type Error = String;
+
+	fn try_from(value: u16) -> Result<Self, Self::Error> {
+		match value {
+			0 => Ok(Version::VERSION_1),
+			1 => Ok(Version::VERSION_2),
+			ver => Err(format!("Unknown version: {ver}")),
+		}
+	}
+}
+
+/// Tags of the optional fields stored since [`Version::VERSION_2`]
+///
+/// Each field is stored as:
+/// +-----+-----------+--------+
+/// | Tag | Value Len | Value  |
+/// +-----+-----------+--------+
+/// |  1  |     4     | varies |
+/// +-----+-----------+--------+
+pub mod field {
+	/// Set on the tags of fields that are required to read the payload.
+	/// Images with an unknown required field are refused, other unknown fields are skipped.
+	pub const REQUIRED: u8 = 0x80;
+
+	/// UTF-8 comment, superseded by [`META`] entries with the `comment` key
+	pub const COMMENT: u8 = 0x01;
+	/// UTF-8 name of the original file, without any directory
+	pub const FILE_NAME: u8 = 0x02;
+	/// Unix mode bits of the original file (u32)
+	pub const FILE_MODE: u8 = 0x03;
+	/// Modification time of the original file: seconds (i64) and nanoseconds (u32) since the unix epoch
+	pub const FILE_MTIME: u8 = 0x04;
+	/// Metadata entry, may be repeated:
+	/// +------+---------+-----+--------+
+	/// | Type | Key Len | Key | Value  |
+	/// +------+---------+-----+--------+
+	/// |  1   |    2    | len | varies |
+	/// +------+---------+-----+--------+
+	/// See [`super::MetaValue`] for the types
+	pub const META: u8 = 0x05;
+	/// Cryptographic digest of the whole payload before compression, even when it is split
+	/// across images:
+	/// +-----------+--------+
+	/// | Algorithm | Digest |
+	/// +-----------+--------+
+	/// |     1     | varies |
+	/// +-----------+--------+
+	/// See [`super::DigestAlgorithm`] for the algorithms
+	pub const DIGEST: u8 = 0x06;
+	/// Compression of the whole payload, the data length and CRC cover the compressed bytes:
+	/// +-------+-----------------+
+	/// | Codec | Original Length |
+	/// +-------+-----------------+
+	/// |   1   |        8        |
+	/// +-------+-----------------+
+	/// See [`crate::compress::Codec`] for the codecs
+	pub const COMPRESSION: u8 = REQUIRED | 0x07;
+	/// Passphrase encryption of the whole payload after compression, the data length and CRC
+	/// cover the encrypted bytes:
+	/// +------+-------+--------+------------+-------------+
+	/// | Salt | Nonce | Memory | Iterations | Parallelism |
+	/// +------+-------+--------+------------+-------------+
+	/// |  16  |  24   |   4    |     4      |      4      |
+	/// +------+-------+--------+------------+-------------+
+	/// See [`crate::crypt::HeaderCrypt`]
+	pub const ENCRYPTION: u8 = REQUIRED | 0x08;
+	/// Public-key encryption of the whole payload after compression, with the file key wrapped
+	/// for each recipient. The data length and CRC cover the encrypted bytes:
+	/// +-------+-----------+-------------+-----+
+	/// | Nonce | Ephemeral | Wrapped Key | ... |
+	/// +-------+-----------+-------------+-----+
+	/// |  24   |    32     |     48      | ... |
+	/// +-------+-----------+-------------+-----+
+	/// See [`crate::crypt::RecipientCrypt`]
+	pub const RECIPIENTS: u8 = REQUIRED | 0x09;
+	/// Ed25519 signature of the header without this field and of the stored payload, so older
+	/// versions can still read signed images:
+	/// +------------+-----------+
+	/// | Public Key | Signature |
+	/// +------------+-----------+
+	/// |     32     |    64     |
+	/// +------------+-----------+
+	/// See [`super::Header::signed_message`]
+	pub const SIGNATURE: u8 = 0x0A;
+	/// Reed-Solomon error correction of the part, the data length and CRC cover the error
+	/// corrected bytes:
+	/// +------------+-----------------+
+	/// | Redundancy | Original Length |
+	/// +------------+-----------------+
+	/// |     1      |        8        |
+	/// +------------+-----------------+
+	/// See [`crate::fec::Fec`]
+	pub const FEC: u8 = REQUIRED | 0x0B;
+	/// Random identifier shared by the parts of a split payload (u64)
+	pub const SET_ID: u8 = 0x0C;
+	/// Fountain coding of the whole payload after encryption, each part holding encoded packets:
+	/// +--------+--------+
+	/// | Config | Needed |
+	/// +--------+--------+
+	/// |   12   |   4    |
+	/// +--------+--------+
+	/// See [`crate::fountain::Fountain`]
+	pub const FOUNTAIN: u8 = REQUIRED | 0x0D;
+	/// File table of an archive payload, the files' data following each other in this order:
+	/// +----------+------+------+------+----------+-----+
+	/// | Path Len | Path | Size | Mode | Checksum | ... |
+	/// +----------+------+------+------+----------+-----+
+	/// |    2     | len  |  8   |  4   |    32    | ... |
+	/// +----------+------+------+------+----------+-----+
+	/// See [`crate::archive::Archive`]
+	pub const ARCHIVE: u8 = REQUIRED | 0x0E;
+}
+
+/// Cryptographic digest algorithm
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+	Sha256,
+	Blake3,
+}
+
+impl DigestAlgorithm {
+	/// Digest length in bytes
+	pub fn output_len(self) -> usize {
+		match self {
+			DigestAlgorithm::Sha256 => 32,
+			DigestAlgorithm::Blake3 => blake3::OUT_LEN,
+		}
+	}
+
+	/// Computes the digest of data given in several slices
+	pub fn digest<'a, I>(self, data: I) -> Vec<u8>
+	where
+		I: IntoIterator<Item = &'a [u8]>,
+	{
+		match self {
+			DigestAlgorithm::Sha256 => {
+				use sha2::Digest;
+				let mut hasher = sha2::Sha256::new();
+				data.into_iter().for_each(|data| hasher.update(data));
+				hasher.finalize().to_vec()
+			}
+			DigestAlgorithm::Blake3 => {
+				let mut hasher = blake3::Hasher::new();
+				data.into_iter().for_each(|data| {
+					hasher.update(data);
+				});
+				hasher.finalize().as_bytes().to_vec()
+			}
+		}
+	}
+}
+
+impl TryFrom<u8> for DigestAlgorithm {
+	type Error = String;
+
+	fn try_from(value: u8) -> Result<Self, Self::Error> {
+		match value {
+			0 => Ok(DigestAlgorithm::Sha256),
+			1 => Ok(DigestAlgorithm::Blake3),
+			alg => Err(format!("Unknown digest algorithm: {alg}")),
+		}
+	}
+}
+
+impl core::fmt::Display for DigestAlgorithm {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match self {
+			DigestAlgorithm::Sha256 => write!(f, "sha256"),
+			DigestAlgorithm::Blake3 => write!(f, "blake3"),
+		}
+	}
+}
+
+impl core::str::FromStr for DigestAlgorithm {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"sha256" => Ok(DigestAlgorithm::Sha256),
+			"blake3" => Ok(DigestAlgorithm::Blake3),
+			_ => Err(format!(
+				"Unknown digest algorithm: `{s}`, expected sha256 or blake3"
+			)),
+		}
+	}
+}
+
+/// Digest of the payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Digest {
+	pub algorithm: DigestAlgorithm,
+	pub value: Vec<u8>,
+}
+
+impl Digest {
+	/// Computes the digest of data given in several slices
+	pub fn new<'a, I>(algorithm: DigestAlgorithm, data: I) -> Self
+	where
+		I: IntoIterator<Item = &'a [u8]>,
+	{
+		Self {
+			algorithm,
+			value: algorithm.digest(data),
+		}
+	}
+
+	/// Checks that data given in several slices matches the digest
+	pub fn verify<'a, I>(&self, data: I) -> Result<(), String>
+	where
+		I: IntoIterator<Item = &'a [u8]>,
+	{
+		let digest = Digest::new(self.algorithm, data);
+		if digest != *self {
+			return Err(format!(
+				"Data digest {digest} does not match header digest {self}"
+			));
+		}
+		Ok(())
+	}
+}
+
+impl core::fmt::Display for Digest {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		write!(f, "{}:", self.algorithm)?;
+		self.value
+			.iter()
+			.try_for_each(|byte| write!(f, "{byte:02x}"))
+	}
+}
+
+/// Key of the metadata entry holding the comment
+pub const COMMENT_KEY: &str = "comment";
+
+/// Typed value of a metadata entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetaValue {
+	/// UTF-8 text, type 0
+	Text(String),
+	/// Signed integer (i64), type 1
+	Integer(i64),
+	/// Raw bytes, type 2
+	Bytes(Vec<u8>),
+}
+
+impl MetaValue {
+	fn kind(&self) -> u8 {
+		match self {
+			MetaValue::Text(_) => 0,
+			MetaValue::Integer(_) => 1,
+			MetaValue::Bytes(_) => 2,
+		}
+	}
+}
+
+impl core::fmt::Display for MetaValue {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match self {
+			MetaValue::Text(text) => write!(f, "{text}"),
+			MetaValue::Integer(value) => write!(f, "{value}"),
+			MetaValue::Bytes(bytes) => bytes.iter().try_for_each(|byte| write!(f, "{byte:02x}")),
+		}
+	}
+}
+
+/// Key/value metadata entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Meta {
+	pub key: String,
+	pub value: MetaValue,
+}
+
+impl Meta {
+	/// Creates a new text entry
+	pub fn text(key: &str, value: String) -> Self {
+		Self {
+			key: key.to_string(),
+			value: MetaValue::Text(value),
+		}
+	}
+}
+
+impl core::fmt::Display for Meta {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match &self.value {
+			MetaValue::Text(_) => write!(f, "{}={}", self.key, self.value),
+			MetaValue::Integer(_) => write!(f, "{}:int={}", self.key, self.value),
+			MetaValue::Bytes(_) => write!(f, "{}:hex={}", self.key, self.value),
+		}
+	}
+}
+
+impl core::str::FromStr for Meta {
+	type Err = String;
+
+	/// Parses `key=text`, `key:int=42` or `key:hex=c0ffee`
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (key, value) = s
+			.split_once('=')
+			.ok_or(format!("Metadata `{s}` should be in the form key=value"))?;
+		let (key, value) =
+			match key.split_once(':') {
+				None => (key, MetaValue::Text(value.to_string())),
+				Some((key, "int")) => (
+					key,
+					MetaValue::Integer(value.parse::<i64>().map_err(|err| {
+						format!("Failed to convert `{value}` to an integer: {err}")
+					})?),
+				),
+				Some((key, "hex")) => {
+					if value.len() % 2 != 0 || !value.is_ascii() {
+						return Err(format!("Invalid hexadecimal value: `{value}`"));
+					}
+					let bytes = (0..value.len())
+						.step_by(2)
+						.map(|i| u8::from_str_radix(&value[i..i + 2], 16))
+						.collect::<Result<Vec<_>, _>>()
+						.map_err(|err| format!("Invalid hexadecimal value `{value}`: {err}"))?;
+					(key, MetaValue::Bytes(bytes))
+				}
+				Some((_, kind)) => return Err(format!("Unknown metadata type: `{kind}`")),
+			};
+
+		if key.is_empty() {
+			return Err(format!("Metadata `{s}` has an empty key"));
+		} else if key.len() > u16::MAX as usize {
+			return Err(format!(
+				"Metadata key is too long, maximum length: {}, got {}",
+				u16::MAX,
+				key.len()
+			));
+		}
+		Ok(Self {
+			key: key.to_string(),
+			value,
+		})
+	}
+}
+
+/// Data header, VERSION_1:
+/// +---------+----------+----------+-------------+---------+
+/// | Version | Data Len | Data CRC | Comment Len | Comment |
+/// +---------+----------+----------+-------------+---------+
+/// |    2    |    4     |    4     |      2      | varies  |
+/// +---------+----------+----------+-------------+---------+
+///
+/// Data header, VERSION_2:
+/// +-------+---------+----------+----------+------+-------+------------+--------+------------+
+/// | Magic | Version | Data Len | Data CRC | Part | Parts | Fields Len | Fields | Header CRC |
+/// +-------+---------+----------+----------+------+-------+------------+--------+------------+
+/// |   8   |    2    |    8     |    4     |  4   |   4   |     4      | varies |     4      |
+/// +-------+---------+----------+----------+------+-------+------------+--------+------------+
+///
+/// The magic is `png_data` or `png_embd` depending on the [`Format`].
+/// The header CRC covers every field from the magic to the optional fields.
+/// In VERSION_1 headers, the only metadata is the `comment` entry.
+/// Since VERSION_2, the header is stored three times in the image.
+/// When a payload is split across several images, each image holds part `Part` out of `Parts`
+/// and its data length and CRC only cover that part. Embeds are never split.
+/// See [`field`] for the optional fields.
+#[derive(Debug, Clone)]
+pub struct Header {
+	/// Unknown for VERSION_1 headers, which have no signature
+	pub format: Option<Format>,
+	pub version: Version,
+	pub data_len: u64,
+	pub data_crc: u32,
+	pub part: u32,
+	pub parts: u32,
+	/// Identifies the parts of the same payload
+	pub set_id: Option<u64>,
+	pub metadata: Vec<Meta>,
+	pub digest: Option<Digest>,
+	pub compression: Option<Compression>,
+	pub crypt: Option<HeaderCrypt>,
+	pub recipients: Option<RecipientCrypt>,
+	pub signature: Option<HeaderSignature>,
+	pub fec: Option<Fec>,
+	pub fountain: Option<Fountain>,
+	pub archive: Option<Archive>,
+	pub file_name: Option<String>,
+	pub file_mode: Option<u32>,
+	pub file_mtime: Option<SystemTime>,
+}
+
+/// Masks stored permissions before restoring them on a file: set-id and sticky bits are dropped,
+/// and only the bits the umask allowed on the newly `created` file are kept, execute bits
+/// following read bits
+pub fn restored_mode(mode: u32, created: u32) -> u32 {
+	let allowed = (created & 0o666) | ((created & 0o444) >> 2);
+	mode & 0o777 & allowed
+}
+
+impl Header {
+	/// Construct a new header from the embedded data
+	pub fn new(
+		format: Format,
+		version: Version,
+		data: &[u8],
+		metadata: Vec<Meta>,
+	) -> Result<Self, String> {
+		if data.len() as u64 > version.max_data_len() {
+			return Err(format!(
+				"Embedded data length: {} is greater than maximum {}",
+				data.len(),
+				version.max_data_len()
+			));
+		}
+		if version == Version::VERSION_1 {
+			match metadata.as_slice() {
+				[] => {}
+				[Meta {
+					key,
+					value: MetaValue::Text(comment),
+				}] if key == COMMENT_KEY => {
+					if comment.len() > u16::MAX as usize {
+						return Err(format!(
+							"Embedded comment is too long, maximum length: {}, got {}",
+							u16::MAX,
+							comment.len()
+						));
+					}
+				}
+				_ => return Err(format!("{version:#?} headers can only hold a comment")),
+			}
+		}
+		for meta in &metadata {
+			let len = meta.key.len()
+				+ match &meta.value {
+					MetaValue::Text(text) => text.len(),
+					MetaValue::Integer(_) => 8,
+					MetaValue::Bytes(bytes) => bytes.len(),
+				};
+			if meta.key.len() > u16::MAX as usize || len > u32::MAX as usize - 3 {
+				return Err(format!("Metadata entry `{}` is too long", meta.key));
+			}
+		}
+
+		Ok(Self {
+			format: Some(format),
+			version,
+			data_len: data.len() as u64,
+			data_crc: Crc::<u32>::new(&crc::CRC_32_CKSUM).checksum(data),
+			part: 0,
+			parts: 1,
+			set_id: None,
+			metadata,
+			digest: None,
+			compression: None,
+			crypt: None,
+			recipients: None,
+			signature: None,
+			fec: None,
+			fountain: None,
+			archive: None,
+			file_name: None,
+			file_mode: None,
+			file_mtime: None,
+		})
+	}
+
+	/// Converts the header to the current version, which can hold every field of older versions.
+	/// The signature no longer matches the header and is dropped.
+	pub fn upgrade(self, format: Format) -> Self {
+		Self {
+			format: Some(format),
+			version: Version::CURRENT,
+			signature: None,
+			..self
+		}
+	}
+
+	/// Gets the bytes authenticated along with an encrypted payload: the encoded header with
+	/// zeroed nonces, without the fields describing how the payload is split and stored, which
+	/// are only known after encryption
+	pub fn associated_data(&self) -> Vec<u8> {
+		let mut data = vec![];
+		Header {
+			data_len: 0,
+			data_crc: 0,
+			part: 0,
+			parts: 1,
+			set_id: None,
+			crypt: self.crypt.clone().map(|crypt| HeaderCrypt {
+				nonce: [0; 24],
+				..crypt
+			}),
+			recipients: self.recipients.clone().map(|recipients| RecipientCrypt {
+				nonce: [0; 24],
+				..recipients
+			}),
+			signature: None,
+			fec: None,
+			fountain: None,
+			..self.clone()
+		}
+		.encode(&mut data);
+		data
+	}
+
+	/// Gets the message signed by [`Self::signature`]: the encoded header without its signature,
+	/// followed by the BLAKE3 hash of the stored payload
+	pub fn signed_message(&self, payload: &[u8]) -> Vec<u8> {
+		let mut message = vec![];
+		Header {
+			signature: None,
+			..self.clone()
+		}
+		.encode(&mut message);
+		message.extend_from_slice(blake3::hash(payload).as_bytes());
+		message
+	}
+
+	/// Signs the header and its stored payload, the header must not change afterwards
+	pub fn sign(&mut self, secret: &[u8; 32], payload: &[u8]) -> Result<(), String> {
+		if self.version == Version::VERSION_1 {
+			return Err(format!("{:#?} headers can not be signed", self.version));
+		}
+		self.signature = Some(HeaderSignature::sign(
+			secret,
+			self.signed_message(payload).as_slice(),
+		));
+		Ok(())
+	}
+
+	/// Verifies the signature of the header and its stored payload. When `signer` is given, the
+	/// header must have been signed with its key.
+	pub fn verify(&self, payload: &[u8], signer: Option<&[u8; 32]>) -> Result<(), String> {
+		match &self.signature {
+			Some(signature) => signature.verify(self.signed_message(payload).as_slice(), signer),
+			None if signer.is_some() => Err("The image is not signed".into()),
+			None => Ok(()),
+		}
+	}
+
+	/// Checks that the header was written by the tool reading `format`
+	pub fn check_format(&self, format: Format) -> Result<(), String> {
+		match self.format {
+			Some(other) if other != format => Err(format!(
+				"This is a {0} image, read it with {0}",
+				other.program()
+			)),
+			_ => Ok(()),
+		}
+	}
+
+	/// Gets the comment entry
+	pub fn comment(&self) -> Option<&str> {
+		self.metadata.iter().find_map(|meta| match &meta.value {
+			MetaValue::Text(comment) if meta.key == COMMENT_KEY => Some(comment.as_str()),
+			_ => None,
+		})
+	}
+
+	/// Encodes the optional fields
+	fn encode_fields(&self, vec: &mut Vec<u8>) {
+		fn push(vec: &mut Vec<u8>, tag: u8, value: &[u8]) {
+			vec.push(tag);
+			vec.extend_from_slice((value.len() as u32).to_le_bytes().as_slice());
+			vec.extend_from_slice(value);
+		}
+
+		for meta in &self.metadata {
+			let mut value = vec![meta.value.kind()];
+			value.extend_from_slice((meta.key.len() as u16).to_le_bytes().as_slice());
+			value.extend_from_slice(meta.key.as_bytes());
+			match &meta.value {
+				MetaValue::Text(text) => value.extend_from_slice(text.as_bytes()),
+				MetaValue::Integer(int) => value.extend_from_slice(int.to_le_bytes().as_slice()),
+				MetaValue::Bytes(bytes) => value.extend_from_slice(bytes.as_slice()),
+			}
+			push(vec, field::META, value.as_slice());
+		}
+		if let Some(digest) = &self.digest {
+			let mut value = vec![digest.algorithm as u8];
+			value.extend_from_slice(digest.value.as_slice());
+			push(vec, field::DIGEST, value.as_slice());
+		}
+		if let Some(compression) = &self.compression {
+			let mut value = vec![compression.codec as u8];
+			value.extend_from_slice(compression.original_len.to_le_bytes().as_slice());
+			push(vec, field::COMPRESSION, value.as_slice());
+		}
+		if let Some(crypt) = &self.crypt {
+			let mut value = crypt.salt.to_vec();
+			value.extend_from_slice(crypt.nonce.as_slice());
+			value.extend_from_slice(crypt.memory.to_le_bytes().as_slice());
+			value.extend_from_slice(crypt.iterations.to_le_bytes().as_slice());
+			value.extend_from_slice(crypt.parallelism.to_le_bytes().as_slice());
+			push(vec, field::ENCRYPTION, value.as_slice());
+		}
+		if let Some(recipients) = &self.recipients {
+			let mut value = recipients.nonce.to_vec();
+			for stanza in &recipients.stanzas {
+				value.extend_from_slice(stanza.ephemeral.as_slice());
+				value.extend_from_slice(stanza.wrapped_key.as_slice());
+			}
+			push(vec, field::RECIPIENTS, value.as_slice());
+		}
+		if let Some(set_id) = self.set_id {
+			push(vec, field::SET_ID, set_id.to_le_bytes().as_slice());
+		}
+		if let Some(archive) = &self.archive {
+			let mut value = vec![];
+			for entry in &archive.entries {
+				value.extend_from_slice((entry.path.len() as u16).to_le_bytes().as_slice());
+				value.extend_from_slice(entry.path.as_bytes());
+				value.extend_from_slice(entry.size.to_le_bytes().as_slice());
+				value.extend_from_slice(entry.mode.to_le_bytes().as_slice());
+				value.extend_from_slice(entry.checksum.as_slice());
+			}
+			push(vec, field::ARCHIVE, value.as_slice());
+		}
+		if let Some(fountain) = &self.fountain {
+			let mut value = fountain.config.to_vec();
+			value.extend_from_slice(fountain.needed.to_le_bytes().as_slice());
+			push(vec, field::FOUNTAIN, value.as_slice());
+		}
+		if let Some(fec) = &self.fec {
+			let mut value = vec![fec.redundancy];
+			value.extend_from_slice(fec.original_len.to_le_bytes().as_slice());
+			push(vec, field::FEC, value.as_slice());
+		}
+		if let Some(name) = &self.file_name {
+			push(vec, field::FILE_NAME, name.as_bytes());
+		}
+		if let Some(mode) = self.file_mode {
+			push(vec, field::FILE_MODE, mode.to_le_bytes().as_slice());
+		}
+		if let Some(mtime) = self.file_mtime {
+			let (secs, nanos) = to_unix_time(mtime);
+			let mut value = secs.to_le_bytes().to_vec();
+			value.extend_from_slice(nanos.to_le_bytes().as_slice());
+			push(vec, field::FILE_MTIME, value.as_slice());
+		}
+		if let Some(signature) = &self.signature {
+			let mut value = signature.public_key.to_vec();
+			value.extend_from_slice(signature.signature.as_slice());
+			push(vec, field::SIGNATURE, value.as_slice());
+		}
+	}
+
+	/// Decodes the optional fields
+	fn decode_fields(&mut self, mut fields: &[u8]) -> Result<(), String> {
+		while !fields.is_empty() {
+			if fields.len() < 5 {
+				return Err("Truncated optional field".into());
+			}
+			let tag = fields[0];
+			let len = u32::from_le_bytes([fields[1], fields[2], fields[3], fields[4]]) as usize;
+			let value = fields
+				.get(5..5 + len)
+				.ok_or(format!("Truncated optional field {tag:#04X}"))?;
+			fields = &fields[5 + len..];
+
+			let text = |value: &[u8]| {
+				String::from_utf8(value.to_vec())
+					.map_err(|e| format!("Failed to retrieve field {tag:#04X}: {e}"))
+			};
+			let fixed = |len: usize| {
+				if value.len() == len {
+					Ok(value)
+				} else {
+					Err(format!(
+						"Field {tag:#04X} should be {len} bytes, got {}",
+						value.len()
+					))
+				}
+			};
+			match tag {
+				field::COMMENT => self.metadata.push(Meta::text(COMMENT_KEY, text(value)?)),
+				field::FILE_NAME => self.file_name = Some(text(value)?),
+				field::FILE_MODE => {
+					self.file_mode = Some(u32::from_le_bytes(fixed(4)?.try_into().unwrap()))
+				}
+				field::FILE_MTIME => {
+					let value = fixed(12)?;
+					self.file_mtime = Some(from_unix_time(
+						i64::from_le_bytes(value[0..8].try_into().unwrap()),
+						u32::from_le_bytes(value[8..12].try_into().unwrap()),
+					)?);
+				}
+				field::META => {
+					if value.len() < 3 {
+						return Err("Truncated metadata entry".into());
+					}
+					let key_len = u16::from_le_bytes([value[1], value[2]]) as usize;
+					let key = text(
+						value
+							.get(3..3 + key_len)
+							.ok_or("Truncated metadata key".to_string())?,
+					)?;
+					let data = &value[3 + key_len..];
+					let value = match value[0] {
+						0 => MetaValue::Text(text(data)?),
+						1 => {
+							MetaValue::Integer(i64::from_le_bytes(data.try_into().map_err(
+								|_| format!("Integer metadata `{key}` should be 8 bytes"),
+							)?))
+						}
+						2 => MetaValue::Bytes(data.to_vec()),
+						kind => return Err(format!("Unknown type {kind} for metadata `{key}`")),
+					};
+					self.metadata.push(Meta { key, value });
+				}
+				field::DIGEST => {
+					let algorithm = DigestAlgorithm::try_from(
+						*value.first().ok_or("Truncated digest".to_string())?,
+					)?;
+					if value.len() != 1 + algorithm.output_len() {
+						return Err(format!(
+							"Digest {algorithm} should be {} bytes, got {}",
+							algorithm.output_len(),
+							value.len() - 1
+						));
+					}
+					self.digest = Some(Digest {
+						algorithm,
+						value: value[1..].to_vec(),
+					});
+				}
+				field::COMPRESSION => {
+					let value = fixed(9)?;
+					self.compression = Some(Compression {
+						codec: Codec::try_from(value[0])?,
+						original_len: u64::from_le_bytes(value[1..9].try_into().unwrap()),
+					});
+				}
+				field::ENCRYPTION => {
+					let value = fixed(52)?;
+					self.crypt = Some(HeaderCrypt {
+						salt: value[0..16].try_into().unwrap(),
+						nonce: value[16..40].try_into().unwrap(),
+						memory: u32::from_le_bytes(value[40..44].try_into().unwrap()),
+						iterations: u32::from_le_bytes(value[44..48].try_into().unwrap()),
+						parallelism: u32::from_le_bytes(value[48..52].try_into().unwrap()),
+					});
+				}
+				field::RECIPIENTS => {
+					if value.len() < 24 + 80 || (value.len() - 24) % 80 != 0 {
+						return Err(format!("Invalid recipients field of {} bytes", value.len()));
+					}
+					self.recipients = Some(RecipientCrypt {
+						nonce: value[0..24].try_into().unwrap(),
+						stanzas: value[24..]
+							.chunks(80)
+							.map(|stanza| Stanza {
+								ephemeral: stanza[0..32].try_into().unwrap(),
+								wrapped_key: stanza[32..80].try_into().unwrap(),
+							})
+							.collect(),
+					});
+				}
+				field::SET_ID => {
+					self.set_id = Some(u64::from_le_bytes(fixed(8)?.try_into().unwrap()))
+				}
+				field::ARCHIVE => {
+					let mut entries = vec![];
+					let mut value = value;
+					while !value.is_empty() {
+						let truncated = || "Truncated archive entry".to_string();
+						let path_len = u16::from_le_bytes(
+							value.get(0..2).ok_or_else(truncated)?.try_into().unwrap(),
+						) as usize;
+						let entry = value.get(2..2 + path_len + 44).ok_or_else(truncated)?;
+						let path = text(&entry[..path_len])?;
+						check_path(path.as_str())?;
+						let entry = &entry[path_len..];
+						entries.push(Entry {
+							path,
+							size: u64::from_le_bytes(entry[0..8].try_into().unwrap()),
+							mode: u32::from_le_bytes(entry[8..12].try_into().unwrap()),
+							checksum: entry[12..44].try_into().unwrap(),
+						});
+						value = &value[2 + path_len + 44..];
+					}
+					self.archive = Some(Archive { entries });
+				}
+				field::FOUNTAIN => {
+					let value = fixed(16)?;
+					self.fountain = Some(Fountain {
+						config: value[0..12].try_into().unwrap(),
+						needed: u32::from_le_bytes(value[12..16].try_into().unwrap()),
+					});
+				}
+				field::FEC => {
+					let value = fixed(9)?;
+					self.fec = Some(Fec {
+						redundancy: value[0],
+						original_len: u64::from_le_bytes(value[1..9].try_into().unwrap()),
+					});
+				}
+				field::SIGNATURE => {
+					let value = fixed(96)?;
+					self.signature = Some(HeaderSignature {
+						public_key: value[0..32].try_into().unwrap(),
+						signature: value[32..96].try_into().unwrap(),
+					});
+				}
+				tag if tag & field::REQUIRED != 0 => {
+					return Err(format!(
+						"Unknown required field {tag:#04X}, this image needs a newer version of this program"
+					))
+				}
+				_ => {}
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Converts a time to seconds and nanoseconds since the unix epoch
+fn to_unix_time(time: SystemTime) -> (i64, u32) {
+	match time.duration_since(SystemTime::UNIX_EPOCH) {
+		Ok(after) => (after.as_secs() as i64, after.subsec_nanos()),
+		Err(err) => {
+			let before = err.duration();
+			if before.subsec_nanos() == 0 {
+				(-(before.as_secs() as i64), 0)
+			} else {
+				(
+					-(before.as_secs() as i64) - 1,
+					1_000_000_000 - before.subsec_nanos(),
+				)
+			}
+		}
+	}
+}
+
+/// Converts seconds and nanoseconds since the unix epoch to a time
+fn from_unix_time(secs: i64, nanos: u32) -> Result<SystemTime, String> {
+	if nanos >= 1_000_000_000 {
+		return Err(format!("Invalid nanoseconds: {nanos}"));
+	}
+	let time = if secs >= 0 {
+		SystemTime::UNIX_EPOCH.checked_add(Duration::new(secs as u64, nanos))
+	} else {
+		SystemTime::UNIX_EPOCH
+			.checked_sub(Duration::from_secs(secs.unsigned_abs()))
+			.and_then(|time| time.checked_add(Duration::from_nanos(nanos as u64)))
+	};
+	time.ok_or(format!("Invalid time: {secs}s {nanos}ns"))
+}
+
+/// Splits a time in its UTC year, month, day, hours, minutes and seconds
+pub fn civil_time(time: SystemTime) -> [i64; 6] {
+	let secs = match time.duration_since(SystemTime::UNIX_EPOCH) {
+		Ok(after) => after.as_secs() as i64,
+		Err(err) => -(err.duration().as_secs_f64().ceil() as i64),
+	};
+
+	// Days to civil date, see <https://howardhinnant.github.io/date_algorithms.html>
+	let (days, time) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z.rem_euclid(146097);
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + i64::from(month <= 2);
+
+	[year, month, day, time / 3600, time / 60 % 60, time % 60]
+}
+
+impl Encode for Header {
+	fn encode(&self, vec: &mut Vec<u8>) {
+		let start = vec.len();
+
+		// Magic
+		if self.version != Version::VERSION_1 {
+			let format = self.format.expect("Headers since VERSION_2 have a format");
+			vec.extend_from_slice(format.magic().as_slice());
+		}
+
+		// Version
+		vec.extend_from_slice((self.version as u16).to_le_bytes().as_slice());
+
+		// Data Len
+		if self.version == Version::VERSION_1 {
+			vec.extend_from_slice((self.data_len as u32).to_le_bytes().as_slice());
+		} else {
+			vec.extend_from_slice(self.data_len.to_le_bytes().as_slice());
+		}
+
+		// Data CRC
+		vec.extend_from_slice(self.data_crc.to_le_bytes().as_slice());
+
+		// Part
+		if self.version != Version::VERSION_1 {
+			vec.extend_from_slice(self.part.to_le_bytes().as_slice());
+			vec.extend_from_slice(self.parts.to_le_bytes().as_slice());
+		}
+
+		if self.version == Version::VERSION_1 {
+			// Comment length
+			let comment_length = self.comment().map_or(0u16, |c| c.len() as u16);
+			vec.extend_from_slice(comment_length.to_le_bytes().as_slice());
+
+			// Comment
+			if let Some(comment) = self.comment() {
+				vec.extend_from_slice(comment.as_bytes());
+			}
+		} else {
+			// Optional fields
+			let mut fields = vec![];
+			self.encode_fields(&mut fields);
+			vec.extend_from_slice((fields.len() as u32).to_le_bytes().as_slice());
+			vec.extend_from_slice(fields.as_slice());
+		}
+
+		// Header CRC
+		if self.version != Version::VERSION_1 {
+			let header_crc = Crc::<u32>::new(&crc::CRC_32_CKSUM).checksum(&vec[start..]);
+			vec.extend_from_slice(header_crc.to_le_bytes().as_slice());
+		}
+	}
+}
+
+/// Reads the header's bytes, keeping them around so they can be read again or checksummed
+struct HeaderReader<'a, I> {
+	it: &'a mut I,
+	read: Vec<u8>,
+	pos: usize,
+}
+
+impl<'a, I> HeaderReader<'a, I>
+where
+	I: Iterator<Item = u8>,
+{
+	pub fn new(it: &'a mut I) -> Self {
+		Self {
+			it,
+			read: vec![],
+			pos: 0,
+		}
+	}
+
+	/// Gets the next header byte
+	pub fn next(&mut self) -> Result<u8, String> {
+		if self.pos == self.read.len() {
+			let byte = self
+				.it
+				.next()
+				.ok_or(format!("Failed to get byte at index: {}", self.pos))?;
+			self.read.push(byte);
+		}
+		self.pos += 1;
+		Ok(self.read[self.pos - 1])
+	}
+
+	/// Gets the next `N` header bytes
+	pub fn bytes<const N: usize>(&mut self) -> Result<[u8; N], String> {
+		let mut result = [0u8; N];
+		for byte in result.iter_mut() {
+			*byte = self.next()?;
+		}
+		Ok(result)
+	}
+
+	/// Gets the next `len` header bytes
+	pub fn vec(&mut self, len: usize) -> Result<Vec<u8>, String> {
+		let mut result = Vec::with_capacity(len.min(u16::MAX as usize));
+		for _ in 0..len {
+			result.push(self.next()?);
+		}
+		Ok(result)
+	}
+
+	/// Restarts reading from the first header byte
+	pub fn rewind(&mut self) { self.pos = 0; }
+
+	/// Computes the CRC of every byte read so far
+	pub fn checksum(&self) -> u32 {
+		Crc::<u32>::new(&crc::CRC_32_CKSUM).checksum(&self.read[..self.pos])
+	}
+}
+
+impl Decode for Header {
+	type Type = Header;
+
+	fn decode<I>(it: &mut I) -> Result<Self::Type, String>
+	where
+		I: Iterator<Item = u8>,
+	{
+		let mut reader = HeaderReader::new(it);
+
+		let magic = reader.bytes::<8>()?;
+		let format = [Format::Data, Format::Embed]
+			.into_iter()
+			.find(|format| format.magic() == magic);
+		let has_magic = format.is_some();
+		if !has_magic {
+			// VERSION_1 headers have no magic and start with their version
+			reader.rewind();
+			if u16::from_le_bytes(reader.bytes()?) != Version::VERSION_1 as u16 {
+				return Err("Missing header signature".into());
+			}
+			reader.rewind();
+		}
+
+		let version = Version::try_from(u16::from_le_bytes(reader.bytes()?))?;
+		if has_magic == (version == Version::VERSION_1) {
+			return Err(format!(
+				"Version {version:#?} does not match the header signature"
+			));
+		}
+		let data_len = if version == Version::VERSION_1 {
+			u32::from_le_bytes(reader.bytes()?) as u64
+		} else {
+			u64::from_le_bytes(reader.bytes()?)
+		};
+		let data_crc = u32::from_le_bytes(reader.bytes()?);
+		let (part, parts) = if version == Version::VERSION_1 {
+			(0, 1)
+		} else {
+			(
+				u32::from_le_bytes(reader.bytes()?),
+				u32::from_le_bytes(reader.bytes()?),
+			)
+		};
+		if part >= parts {
+			return Err(format!("Invalid part {part} out of {parts}"));
+		}
+
+		let mut header = Header {
+			format,
+			version,
+			data_len,
+			data_crc,
+			part,
+			parts,
+			set_id: None,
+			metadata: vec![],
+			digest: None,
+			compression: None,
+			crypt: None,
+			recipients: None,
+			signature: None,
+			fec: None,
+			fountain: None,
+			archive: None,
+			file_name: None,
+			file_mode: None,
+			file_mtime: None,
+		};
+
+		let fields = if version == Version::VERSION_1 {
+			let comment_length = u16::from_le_bytes(reader.bytes()?);
+			if comment_length != 0 {
+				let comment_data = reader.vec(comment_length as usize)?;
+				header.metadata.push(Meta::text(
+					COMMENT_KEY,
+					String::from_utf8(comment_data)
+						.map_err(|e| format!("Failed to retrieve comment: {e}"))?,
+				));
+			}
+			vec![]
+		} else {
+			let fields_length = u32::from_le_bytes(reader.bytes()?);
+			reader.vec(fields_length as usize)?
+		};
+
+		if version != Version::VERSION_1 {
+			let crc = reader.checksum();
+			let header_crc = u32::from_le_bytes(reader.bytes()?);
+			if crc != header_crc {
+				return Err(format!(
+					"Header CRC[{crc:X}] does not match stored CRC[{header_crc:X}], the header is damaged"
+				));
+			}
+		}
+
+		// Only trust the fields once the header CRC has been checked
+		header.decode_fields(fields.as_slice())?;
+
+		Ok(header)
+	}
+}
+
+/// Positions of the header copies in the image data of png_data images since
+/// [`Version::VERSION_2`]: at the start, the middle and the end of the image data, the last copy
+/// being stored backwards
+pub fn header_copies(len: usize, header_len: usize) -> [Range<usize>; 3] {
+	[
+		0..header_len,
+		len / 2..len / 2 + header_len,
+		len - header_len..len,
+	]
+}
+
+/// Decodes a header copy, returning the header and its encoded length
+pub fn decode_copy<I: ExactSizeIterator<Item = u8>>(mut it: I) -> Result<(Header, usize), String> {
+	let len = it.len();
+	let header = Header::decode(&mut it)?;
+	Ok((header, len - it.len()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::sign;
+
+	fn decode(data: &[u8]) -> Result<Header, String> { Header::decode(&mut data.iter().copied()) }
+
+	#[test]
+	fn restored_modes() {
+		assert_eq!(restored_mode(0o755, 0o644), 0o755);
+		assert_eq!(restored_mode(0o4755, 0o644), 0o755);
+		assert_eq!(restored_mode(0o7777, 0o644), 0o755);
+		// umask 077
+		assert_eq!(restored_mode(0o755, 0o600), 0o700);
+		assert_eq!(restored_mode(0o666, 0o640), 0o640);
+	}
+
+	#[test]
+	fn roundtrip() {
+		for version in [Version::VERSION_1, Version::VERSION_2] {
+			let comment = vec![Meta::text(COMMENT_KEY, "(.txt)".into())];
+			let header = Header::new(Format::Data, version, b"payload", comment).unwrap();
+			let mut data = vec![];
+			header.encode(&mut data);
+
+			let decoded = decode(&data).unwrap();
+			assert_eq!(decoded.version, version);
+			assert_eq!(decoded.data_len, 7);
+			assert_eq!(decoded.data_crc, header.data_crc);
+			assert_eq!(decoded.comment(), Some("(.txt)"));
+		}
+	}
+
+	#[test]
+	fn formats() {
+		for (format, other) in [(Format::Data, Format::Embed), (Format::Embed, Format::Data)] {
+			let header = Header::new(format, Version::CURRENT, b"payload", vec![]).unwrap();
+			let mut data = vec![];
+			header.encode(&mut data);
+
+			let decoded = decode(&data).unwrap();
+			assert_eq!(decoded.format, Some(format));
+			assert!(decoded.check_format(format).is_ok());
+			assert!(decoded.check_format(other).is_err());
+		}
+
+		// VERSION_1 headers can be read by both tools
+		let header = Header::new(Format::Embed, Version::VERSION_1, b"payload", vec![]).unwrap();
+		let mut data = vec![];
+		header.encode(&mut data);
+		let decoded = decode(&data).unwrap();
+		assert_eq!(decoded.format, None);
+		assert!(decoded.check_format(Format::Data).is_ok());
+	}
+
+	#[test]
+	fn metadata() {
+		let metadata = [
+			"mime=application/pdf",
+			"ticket:int=-42",
+			"host:hex=c0ffee",
+			"mime=text/plain",
+		]
+		.iter()
+		.map(|meta| meta.parse::<Meta>().unwrap())
+		.collect::<Vec<_>>();
+		assert_eq!(metadata[1].value, MetaValue::Integer(-42));
+		assert_eq!(metadata[2].value, MetaValue::Bytes(vec![0xc0, 0xff, 0xee]));
+		assert_eq!(metadata[2].to_string(), "host:hex=c0ffee");
+		assert!("novalue".parse::<Meta>().is_err());
+		assert!("key:float=1.0".parse::<Meta>().is_err());
+
+		assert!(Header::new(
+			Format::Data,
+			Version::VERSION_1,
+			b"payload",
+			metadata.clone()
+		)
+		.is_err());
+		let header = Header::new(
+			Format::Data,
+			Version::VERSION_2,
+			b"payload",
+			metadata.clone(),
+		)
+		.unwrap();
+		let mut data = vec![];
+		header.encode(&mut data);
+
+		let decoded = decode(&data).unwrap();
+		assert_eq!(decoded.metadata, metadata);
+		assert_eq!(decoded.comment(), None);
+	}
+
+	#[test]
+	fn parts() {
+		let mut header = Header::new(Format::Data, Version::VERSION_2, b"payload", vec![]).unwrap();
+		header.part = 2;
+		header.parts = 3;
+		let mut data = vec![];
+		header.encode(&mut data);
+
+		let decoded = decode(&data).unwrap();
+		assert_eq!((decoded.part, decoded.parts), (2, 3));
+
+		header.part = 3;
+		data.clear();
+		header.encode(&mut data);
+		assert!(decode(&data).is_err());
+
+		let mut header = Header::new(Format::Data, Version::CURRENT, b"payload", vec![]).unwrap();
+		header.part = 1;
+		header.parts = 2;
+		header.set_id = Some(0x0123_4567_89AB_CDEF);
+		data.clear();
+		header.encode(&mut data);
+		assert_eq!(decode(&data).unwrap().set_id, Some(0x0123_4567_89AB_CDEF));
+	}
+
+	#[test]
+	fn file_fields() {
+		let mut header = Header::new(Format::Data, Version::VERSION_2, b"payload", vec![]).unwrap();
+		header.file_name = Some("file.tar".into());
+		header.file_mode = Some(0o100644);
+		header.file_mtime = Some(SystemTime::UNIX_EPOCH - Duration::new(10, 250));
+		let mut data = vec![];
+		header.encode(&mut data);
+
+		let decoded = decode(&data).unwrap();
+		assert_eq!(decoded.metadata, vec![]);
+		assert_eq!(decoded.file_name.as_deref(), Some("file.tar"));
+		assert_eq!(decoded.file_mode, Some(0o100644));
+		assert_eq!(decoded.file_mtime, header.file_mtime);
+	}
+
+	#[test]
+	fn digest() {
+		for algorithm in [DigestAlgorithm::Sha256, DigestAlgorithm::Blake3] {
+			let mut header =
+				Header::new(Format::Data, Version::VERSION_2, b"payload", vec![]).unwrap();
+			header.digest = Some(Digest::new(algorithm, [b"pay".as_slice(), b"load"]));
+			let mut data = vec![];
+			header.encode(&mut data);
+
+			let digest = decode(&data).unwrap().digest.unwrap();
+			assert_eq!(Some(&digest), header.digest.as_ref());
+			assert!(digest.verify([b"payload".as_slice()]).is_ok());
+			assert!(digest.verify([b"pay1oad".as_slice()]).is_err());
+		}
+
+		let digest = Digest::new(DigestAlgorithm::Sha256, [b"abc".as_slice()]);
+		assert_eq!(
+			digest.to_string(),
+			"sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+		);
+	}
+
+	#[test]
+	fn associated_data() {
+		let mut header = Header::new(
+			Format::Data,
+			Version::CURRENT,
+			b"payload",
+			vec![Meta::text(COMMENT_KEY, "(.txt)".into())],
+		)
+		.unwrap();
+		header.crypt = Some(HeaderCrypt {
+			salt: [1; 16],
+			nonce: [2; 24],
+			memory: 64,
+			iterations: 1,
+			parallelism: 1,
+		});
+		let data = header.associated_data();
+
+		// The nonce and the parts' layout are not known before encryption
+		let mut stored = header.clone();
+		stored.crypt.as_mut().unwrap().nonce = [3; 24];
+		stored.data_len = 30_000;
+		stored.data_crc = 0x1234_5678;
+		stored.part = 1;
+		stored.parts = 2;
+		stored.set_id = Some(42);
+		stored.fec = Some(Fec {
+			redundancy: 25,
+			original_len: 7,
+		});
+		assert_eq!(stored.associated_data(), data);
+
+		let mut tampered = header.clone();
+		tampered.file_name = Some("other".into());
+		assert_ne!(tampered.associated_data(), data);
+		tampered = header.clone();
+		tampered.metadata.clear();
+		assert_ne!(tampered.associated_data(), data);
+	}
+
+	#[test]
+	fn fields() {
+		let (payload, compression) = Compression::compress(Codec::Zstd, b"payload").unwrap();
+		let mut header = Header::new(Format::Data, Version::CURRENT, &payload, vec![]).unwrap();
+		header.compression = Some(compression);
+		header.crypt = Some(HeaderCrypt {
+			salt: [1; 16],
+			nonce: [2; 24],
+			memory: 64,
+			iterations: 1,
+			parallelism: 1,
+		});
+		header.recipients = Some(RecipientCrypt {
+			nonce: [3; 24],
+			stanzas: vec![
+				Stanza {
+					ephemeral: [4; 32],
+					wrapped_key: [5; 48],
+				};
+				2
+			],
+		});
+		header.fec = Some(Fec {
+			redundancy: 25,
+			original_len: 7,
+		});
+		header.fountain = Some(Fountain {
+			config: [6; 12],
+			needed: 2,
+		});
+		header.archive = Some(Archive {
+			entries: vec![Entry {
+				path: "dir/a.txt".into(),
+				size: payload.len() as u64,
+				mode: 0o644,
+				checksum: [7; 32],
+			}],
+		});
+		let mut data = vec![];
+		header.encode(&mut data);
+
+		let decoded = decode(&data).unwrap();
+		assert_eq!(decoded.compression, header.compression);
+		assert_eq!(decoded.crypt, header.crypt);
+		assert_eq!(decoded.recipients, header.recipients);
+		assert_eq!(decoded.fec, header.fec);
+		assert_eq!(decoded.fountain, header.fountain);
+		assert_eq!(decoded.archive, header.archive);
+
+		// Archive paths are checked on decode
+		header.archive.as_mut().unwrap().entries[0].path = "../a.txt".into();
+		data.clear();
+		header.encode(&mut data);
+		assert!(decode(&data).is_err());
+	}
+
+	#[test]
+	fn signature() {
+		let (secret, public) = sign::generate_keys();
+		let (_, other) = sign::generate_keys();
+		let mut header = Header::new(Format::Data, Version::CURRENT, b"payload", vec![]).unwrap();
+		assert!(header.verify(b"payload", None).is_ok());
+		assert!(header.verify(b"payload", Some(&public)).is_err());
+
+		header.sign(&secret, b"payload").unwrap();
+		let mut data = vec![];
+		header.encode(&mut data);
+		let header = decode(&data).unwrap();
+		assert_eq!(header.signature.as_ref().unwrap().public_key, public);
+		assert!(header.verify(b"payload", None).is_ok());
+		assert!(header.verify(b"payload", Some(&public)).is_ok());
+		assert!(header.verify(b"payload", Some(&other)).is_err());
+		assert!(header.verify(b"tampered", None).is_err());
+
+		let mut tampered = header.clone();
+		tampered.file_name = Some("other".into());
+		assert!(tampered.verify(b"payload", None).is_err());
+		assert!(header.upgrade(Format::Embed).signature.is_none());
+	}
+
+	#[test]
+	fn unknown_fields() {
+		let header = Header::new(Format::Data, Version::VERSION_2, b"payload", vec![]).unwrap();
+		let mut data = vec![];
+		header.encode(&mut data);
+
+		// Append an unknown field and fix up the lengths and CRC
+		let with_field = |tag: u8| {
+			let mut data = data[..data.len() - 4].to_vec();
+			data.extend_from_slice(&[tag, 1, 0, 0, 0, 0xAA]);
+			data[30..34].copy_from_slice(6u32.to_le_bytes().as_slice());
+			let crc = Crc::<u32>::new(&crc::CRC_32_CKSUM).checksum(data.as_slice());
+			data.extend_from_slice(crc.to_le_bytes().as_slice());
+			data
+		};
+		assert!(decode(&with_field(0x7F)).is_ok());
+		assert!(decode(&with_field(0xFF)).is_err());
+	}
+
+	#[test]
+	fn not_png_data() {
+		assert!(decode(&[0x12; 64]).is_err());
+
+		let header = Header::new(Format::Data, Version::VERSION_2, b"payload", vec![]).unwrap();
+		let mut data = vec![];
+		header.encode(&mut data);
+		data[12] ^= 0x01;
+		assert!(decode(&data).is_err());
+	}
+}
@@ -0,0 +1,17 @@
+//! Image formats shared by `png_data` and `png_embed`
+pub mod archive;
+pub mod block;
+pub mod carrier;
+pub mod compress;
+pub mod crypt;
+pub mod embed;
+pub mod ent;
+pub mod fec;
+pub mod fountain;
+pub mod header;
+pub mod paper;
+pub mod polyglot;
+pub mod rescan;
+pub mod shape;
+pub mod sign;
+pub mod visualize;
@@ -0,0 +1,542 @@
+use std::ops::Range;
+
+use crc::Crc;
+use rand::Rng;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::fec::Fec;
+
+/// Modules of white around the code
+const QUIET_ZONE: usize = 4;
+/// Modules of a finder pattern's side
+const FINDER: usize = 7;
+/// Smallest side of a code, in modules
+const MIN_SIDE: usize = 21;
+/// Largest side of a printed image, in pixels
+const MAX_PIXELS: usize = 32768;
+/// Length of the frame information: redundancy, payload length and payload CRC
+const INFO_LEN: usize = 9;
+
+/// Grayscale picture, one byte per pixel
+pub struct Bitmap {
+	pub width: u32,
+	pub height: u32,
+	pub pixels: Vec<u8>,
+}
+
+impl Bitmap {
+	fn get(&self, x: usize, y: usize) -> u8 { self.pixels[y * self.width as usize + x] }
+}
+
+/// Checks whether a module holds data, the other modules belong to the finder patterns and
+/// their separators
+fn is_data(side: usize, x: usize, y: usize) -> bool {
+	let first = |pos: usize| pos <= FINDER;
+	let last = |pos: usize| pos + FINDER + 1 >= side;
+	!(first(x) && (first(y) || last(y)) || last(x) && first(y))
+}
+
+/// Gets the number of bytes held by a code
+fn capacity(side: usize) -> usize { (side * side - 3 * (FINDER + 1).pow(2)) / 8 }
+
+/// Gets the three copies of the frame information among the code's bytes
+fn info_copies(len: usize) -> [Range<usize>; 3] {
+	let middle = (len - INFO_LEN) / 2;
+	[0..INFO_LEN, middle..middle + INFO_LEN, len - INFO_LEN..len]
+}
+
+/// Gets the positions of the encoded payload among the code's bytes
+fn payload_positions(len: usize) -> impl Iterator<Item = usize> {
+	let copies = info_copies(len);
+	(0..len).filter(move |pos| !copies.iter().any(|copy| copy.contains(pos)))
+}
+
+/// Draws a payload as a printable code: each bit is a square module of `module` pixels,
+/// surrounded by a quiet zone, with finder patterns in three corners.
+///
+/// The payload is protected by Reed-Solomon codewords with `redundancy` percent of parity,
+/// and described by three copies of its length, redundancy and CRC
+pub fn encode(data: &[u8], redundancy: u8, module: u32) -> Result<Bitmap, String> {
+	if module == 0 {
+		return Err("Modules must be at least one pixel wide".into());
+	}
+	let (encoded, fec) = Fec::encode(redundancy, data)?;
+	let len = encoded.len() + 3 * INFO_LEN;
+	let side = (MIN_SIDE..).find(|side| capacity(*side) >= len).unwrap();
+	let size = (side + 2 * QUIET_ZONE) * module as usize;
+	if size > MAX_PIXELS || data.len() > u32::MAX as usize {
+		return Err(format!(
+			"The payload needs a {size}x{size} image, maximum: {MAX_PIXELS}x{MAX_PIXELS}"
+		));
+	}
+
+	// Unused bytes and modules are filled with a fixed noise
+	let mut rng = ChaCha8Rng::seed_from_u64(0);
+	let mut bytes = vec![0u8; capacity(side)];
+	rng.fill(bytes.as_mut_slice());
+	let mut info = vec![fec.redundancy];
+	info.extend_from_slice(&(data.len() as u32).to_le_bytes());
+	info.extend_from_slice(
+		&Crc::<u32>::new(&crc::CRC_32_CKSUM)
+			.checksum(data)
+			.to_le_bytes(),
+	);
+	for copy in info_copies(bytes.len()) {
+		bytes[copy].copy_from_slice(info.as_slice());
+	}
+	for (pos, byte) in payload_positions(bytes.len()).zip(encoded) {
+		bytes[pos] = byte;
+	}
+
+	let mut bits = bytes
+		.iter()
+		.flat_map(|byte| (0..8).rev().map(move |bit| byte >> bit & 1 == 1));
+	let mut modules = vec![false; side * side];
+	for y in 0..side {
+		for x in 0..side {
+			if is_data(side, x, y) {
+				modules[y * side + x] = bits.next().unwrap_or_else(|| rng.gen());
+			}
+		}
+	}
+	for (fx, fy) in [(0, 0), (side - FINDER, 0), (0, side - FINDER)] {
+		for y in 0..FINDER {
+			for x in 0..FINDER {
+				let ring = x.min(y).min(FINDER - 1 - x).min(FINDER - 1 - y);
+				modules[(fy + y) * side + fx + x] = ring != 1;
+			}
+		}
+	}
+
+	let module = module as usize;
+	let mut pixels = vec![255u8; size * size];
+	for (i, _) in modules.iter().enumerate().filter(|(_, black)| **black) {
+		let (x, y) = (
+			(i % side + QUIET_ZONE) * module,
+			(i / side + QUIET_ZONE) * module,
+		);
+		for row in y..y + module {
+			pixels[row * size + x..row * size + x + module].fill(0);
+		}
+	}
+	Ok(Bitmap {
+		width: size as u32,
+		height: size as u32,
+		pixels,
+	})
+}
+
+/// Blurs a picture with a 3x3 box, smoothing the noise of scans
+fn blur(image: &Bitmap) -> Bitmap {
+	let (width, height) = (image.width as usize, image.height as usize);
+	let mut pixels = vec![0u8; width * height];
+	for y in 0..height {
+		for x in 0..width {
+			let (mut sum, mut count) = (0usize, 0usize);
+			for ny in y.saturating_sub(1)..(y + 2).min(height) {
+				for nx in x.saturating_sub(1)..(x + 2).min(width) {
+					sum += image.get(nx, ny) as usize;
+					count += 1;
+				}
+			}
+			pixels[y * width + x] = (sum / count) as u8;
+		}
+	}
+	Bitmap {
+		width: image.width,
+		height: image.height,
+		pixels,
+	}
+}
+
+/// Gets the threshold between black and white with Otsu's method
+fn threshold(image: &Bitmap) -> u8 {
+	let mut histogram = [0usize; 256];
+	for pixel in &image.pixels {
+		histogram[*pixel as usize] += 1;
+	}
+	let total = image.pixels.len() as f64;
+	let sum = (0..256)
+		.map(|i| i as f64 * histogram[i] as f64)
+		.sum::<f64>();
+
+	let (mut best, mut best_variance) = (128, 0.0);
+	let (mut weight, mut weighted) = (0.0, 0.0);
+	for (i, count) in histogram.iter().enumerate() {
+		weight += *count as f64;
+		weighted += i as f64 * *count as f64;
+		if weight == 0.0 || weight == total {
+			continue;
+		}
+		let dark = weighted / weight;
+		let light = (sum - weighted) / (total - weight);
+		let variance = weight * (total - weight) * (dark - light).powi(2);
+		if variance > best_variance {
+			(best, best_variance) = (i as u8 + 1, variance);
+		}
+	}
+	best
+}
+
+/// Gets the module size of runs in the 1:1:3:1:1 ratio of a finder pattern
+fn finder_ratio(runs: [usize; 5]) -> Option<f32> {
+	let module = runs.iter().sum::<usize>() as f32 / FINDER as f32;
+	let variance = module / 2.0;
+	let ok = module >= 1.0
+		&& [0, 1, 3, 4]
+			.iter()
+			.all(|i| (runs[*i] as f32 - module).abs() < variance)
+		&& (runs[2] as f32 - 3.0 * module).abs() < 3.0 * variance;
+	ok.then_some(module)
+}
+
+/// Candidate center of a finder pattern
+#[derive(Debug, Clone, Copy)]
+struct Finder {
+	x: f32,
+	y: f32,
+	module: f32,
+	/// Number of rows that detected it
+	count: usize,
+}
+
+/// Finder pattern detection on a binarized picture
+struct Detector<'a> {
+	image: &'a Bitmap,
+	threshold: u8,
+}
+
+impl Detector<'_> {
+	fn is_black(&self, x: usize, y: usize) -> bool { self.image.get(x, y) < self.threshold }
+
+	/// Measures a finder pattern through a point, along the rows when `horizontal` or along the
+	/// columns. Returns the center of the pattern on that axis and its module size
+	fn cross_check(&self, x: usize, y: usize, horizontal: bool) -> Option<(f32, f32)> {
+		let (pos, len) = match horizontal {
+			true => (x, self.image.width as usize),
+			false => (y, self.image.height as usize),
+		};
+		let black = |i: usize| match horizontal {
+			true => self.is_black(i, y),
+			false => self.is_black(x, i),
+		};
+		if !black(pos) {
+			return None;
+		}
+
+		// Center, white and black runs on each side of the point
+		let mut before = [0usize; 3];
+		let mut i = pos as isize;
+		for (run, color) in before.iter_mut().zip([true, false, true]) {
+			while i >= 0 && black(i as usize) == color {
+				*run += 1;
+				i -= 1;
+			}
+		}
+		let mut after = [0usize; 3];
+		let mut i = pos + 1;
+		for (run, color) in after.iter_mut().zip([true, false, true]) {
+			while i < len && black(i) == color {
+				*run += 1;
+				i += 1;
+			}
+		}
+
+		let center = before[0] + after[0];
+		let module = finder_ratio([before[2], before[1], center, after[1], after[2]])?;
+		Some(((pos + 1 - before[0]) as f32 + center as f32 / 2.0, module))
+	}
+
+	/// Scans every row for finder patterns, confirming them along their column
+	fn finders(&self) -> Vec<Finder> {
+		let mut finders: Vec<Finder> = vec![];
+		for y in 0..self.image.height as usize {
+			// Runs of alternating colors, starting with a black run
+			let mut runs = vec![];
+			let mut x = 0;
+			while x < self.image.width as usize {
+				let (start, color) = (x, self.is_black(x, y));
+				while x < self.image.width as usize && self.is_black(x, y) == color {
+					x += 1;
+				}
+				if color || !runs.is_empty() {
+					runs.push((start, x - start));
+				}
+			}
+
+			for window in runs.windows(5).step_by(2) {
+				let lens = [0, 1, 2, 3, 4].map(|i| window[i].1);
+				if finder_ratio(lens).is_none() {
+					continue;
+				}
+				let cx = window[2].0 + window[2].1 / 2;
+				let Some((cy, vertical)) = self.cross_check(cx, y, false) else {
+					continue;
+				};
+				let Some((cx, horizontal)) = self.cross_check(cx, cy as usize, true) else {
+					continue;
+				};
+				let module = (vertical + horizontal) / 2.0;
+
+				match finders.iter_mut().find(|finder| {
+					(finder.x - cx).abs() < module * 2.0
+						&& (finder.y - cy).abs() < module * 2.0
+						&& (finder.module - module).abs() < module / 2.0
+				}) {
+					Some(finder) => {
+						let count = finder.count as f32;
+						finder.x = (finder.x * count + cx) / (count + 1.0);
+						finder.y = (finder.y * count + cy) / (count + 1.0);
+						finder.module = (finder.module * count + module) / (count + 1.0);
+						finder.count += 1;
+					}
+					None => finders.push(Finder {
+						x: cx,
+						y: cy,
+						module,
+						count: 1,
+					}),
+				}
+			}
+		}
+		finders
+	}
+}
+
+/// Picks the top-left, top-right and bottom-left finder patterns among the candidates: the
+/// three that best form an isosceles right angle
+fn corners(finders: &[Finder]) -> Option<[Finder; 3]> {
+	let mut finders = finders
+		.iter()
+		.filter(|finder| finder.count >= 2)
+		.copied()
+		.collect::<Vec<_>>();
+	finders.sort_by_key(|finder| std::cmp::Reverse(finder.count));
+	finders.truncate(10);
+
+	let mut best: Option<(f32, [Finder; 3])> = None;
+	for a in 0..finders.len() {
+		for b in a + 1..finders.len() {
+			for c in b + 1..finders.len() {
+				for [corner, p, q] in
+					[[a, b, c], [b, a, c], [c, a, b]].map(|i| i.map(|i| finders[i]))
+				{
+					let (px, py) = (p.x - corner.x, p.y - corner.y);
+					let (qx, qy) = (q.x - corner.x, q.y - corner.y);
+					let (lp, lq) = (px.hypot(py), qx.hypot(qy));
+					if lp.min(lq) < corner.module * FINDER as f32 {
+						continue;
+					}
+					let modules = [corner.module, p.module, q.module];
+					let score = ((px * qx + py * qy) / (lp * lq)).abs()
+						+ (lp - lq).abs() / lp.max(lq)
+						+ modules.iter().fold(0.0f32, |max, m| max.max(*m))
+							/ modules.iter().fold(f32::MAX, |min, m| min.min(*m))
+						- 1.0;
+					if best.is_none_or(|(best, _)| score < best) {
+						// The top-right pattern comes first clockwise
+						let corners = match px * qy - py * qx > 0.0 {
+							true => [corner, p, q],
+							false => [corner, q, p],
+						};
+						best = Some((score, corners));
+					}
+				}
+			}
+		}
+	}
+	best.filter(|(score, _)| *score < 0.5)
+		.map(|(_, corners)| corners)
+}
+
+/// Reads the code's bytes, sampling the picture at the center of each module
+fn sample(image: &Bitmap, threshold: u8, corners: &[Finder; 3], side: usize) -> Vec<u8> {
+	let [tl, tr, bl] = corners;
+	let span = (side - FINDER) as f32;
+	let module = (tl.module + tr.module + bl.module) / 3.0;
+	let radius = (module / 4.0) as isize;
+
+	let mut bits = vec![];
+	for y in 0..side {
+		for x in 0..side {
+			if !is_data(side, x, y) {
+				continue;
+			}
+			// Finder centers are at the center of their 4th module
+			let (u, v) = ((x as f32 - 3.0) / span, (y as f32 - 3.0) / span);
+			let px = tl.x + (tr.x - tl.x) * u + (bl.x - tl.x) * v;
+			let py = tl.y + (tr.y - tl.y) * u + (bl.y - tl.y) * v;
+
+			let (mut sum, mut count) = (0usize, 0usize);
+			for dy in -radius..=radius {
+				for dx in -radius..=radius {
+					let (sx, sy) = (px as isize + dx, py as isize + dy);
+					if sx >= 0 && sy >= 0 && (sx as u32) < image.width && (sy as u32) < image.height
+					{
+						sum += image.get(sx as usize, sy as usize) as usize;
+						count += 1;
+					}
+				}
+			}
+			bits.push(count == 0 || sum < threshold as usize * count);
+		}
+	}
+	bits.chunks_exact(8)
+		.map(|bits| bits.iter().fold(0u8, |byte, bit| byte << 1 | *bit as u8))
+		.collect()
+}
+
+/// Recovers the payload from the code's bytes, returning it with the number of corrected
+/// symbols
+fn read_payload(bytes: &[u8]) -> Result<(Vec<u8>, usize), String> {
+	let [a, b, c] = info_copies(bytes.len()).map(|copy| &bytes[copy]);
+	let info = (0..INFO_LEN)
+		.map(|i| a[i] & b[i] | a[i] & c[i] | b[i] & c[i])
+		.collect::<Vec<_>>();
+	let fec = Fec {
+		redundancy: info[0],
+		original_len: u32::from_le_bytes(info[1..5].try_into().unwrap()) as u64,
+	};
+	let crc = u32::from_le_bytes(info[5..9].try_into().unwrap());
+	let len = fec.encoded_len()?;
+	if len + 3 * INFO_LEN > bytes.len() {
+		return Err("Invalid payload length".into());
+	}
+
+	let mut encoded = payload_positions(bytes.len())
+		.take(len)
+		.map(|pos| bytes[pos])
+		.collect::<Vec<_>>();
+	let corrected = fec.repair(encoded.as_mut_slice())?;
+	let data = fec.decode(encoded.as_slice())?;
+	if Crc::<u32>::new(&crc::CRC_32_CKSUM).checksum(&data) != crc {
+		return Err("The payload's CRC does not match".into());
+	}
+	Ok((data, corrected))
+}
+
+/// Reads a code from a picture that may have been printed and scanned: rescaled, slightly
+/// rotated or noisy. Returns the payload with the number of corrected symbols
+pub fn decode(image: &Bitmap) -> Result<(Vec<u8>, usize), String> {
+	let blurred = blur(image);
+	let threshold = threshold(&blurred);
+	let finders = Detector {
+		image: &blurred,
+		threshold,
+	}
+	.finders();
+	let corners = corners(finders.as_slice()).ok_or("Could not find the code's finder patterns")?;
+
+	// Try the sides closest to the estimate first
+	let [tl, tr, bl] = &corners;
+	let module = (tl.module + tr.module + bl.module) / 3.0;
+	let distance = ((tr.x - tl.x).hypot(tr.y - tl.y) + (bl.x - tl.x).hypot(bl.y - tl.y)) / 2.0;
+	let estimate = (distance / module).round() as isize + FINDER as isize;
+	let mut error = "Could not find the code's finder patterns".to_string();
+	let range = 3 + estimate / 10;
+	for side in (0..=range * 2).map(|i| estimate + if i % 2 == 0 { i / 2 } else { -i / 2 - 1 }) {
+		if side < MIN_SIDE as isize || side as usize * module as usize > MAX_PIXELS {
+			continue;
+		}
+		let bytes = sample(&blurred, threshold, &corners, side as usize);
+		match read_payload(bytes.as_slice()) {
+			Ok(result) => return Ok(result),
+			Err(err) => error = format!("Could not read the code: {err}"),
+		}
+	}
+	Err(error)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Simulates a scan: scales, rotates by `angle` degrees and adds gaussian noise
+	fn scan(image: &Bitmap, scale: f32, angle: f32, noise: f32, seed: u64) -> Bitmap {
+		let mut rng = ChaCha8Rng::seed_from_u64(seed);
+		let (sin, cos) = angle.to_radians().sin_cos();
+		let (width, height) = (image.width as f32, image.height as f32);
+		let size = ((width + height) * scale) as usize;
+		let center = size as f32 / 2.0;
+
+		let mut pixels = vec![0u8; size * size];
+		for y in 0..size {
+			for x in 0..size {
+				// Inverse transform, with bilinear sampling over white margins
+				let (dx, dy) = (x as f32 - center, y as f32 - center);
+				let sx = (dx * cos + dy * sin) / scale + width / 2.0;
+				let sy = (dy * cos - dx * sin) / scale + height / 2.0;
+				let get = |x: f32, y: f32| {
+					if x < 0.0 || y < 0.0 || x >= width || y >= height {
+						255.0
+					} else {
+						image.get(x as usize, y as usize) as f32
+					}
+				};
+				let (fx, fy) = (sx - sx.floor(), sy - sy.floor());
+				let value = get(sx, sy) * (1.0 - fx) * (1.0 - fy)
+					+ get(sx + 1.0, sy) * fx * (1.0 - fy)
+					+ get(sx, sy + 1.0) * (1.0 - fx) * fy
+					+ get(sx + 1.0, sy + 1.0) * fx * fy;
+
+				let (u, v) = (rng.gen::<f32>().max(f32::MIN_POSITIVE), rng.gen::<f32>());
+				let gaussian = (-2.0 * u.ln()).sqrt() * (std::f32::consts::TAU * v).cos();
+				pixels[y * size + x] = (value + gaussian * noise).clamp(0.0, 255.0) as u8;
+			}
+		}
+		Bitmap {
+			width: size as u32,
+			height: size as u32,
+			pixels,
+		}
+	}
+
+	#[test]
+	fn roundtrip() {
+		let mut data = vec![0u8; 600];
+		ChaCha8Rng::seed_from_u64(1).fill(data.as_mut_slice());
+		let code = encode(&data, 100, 4).unwrap();
+		assert_eq!(decode(&code), Ok((data, 0)));
+
+		assert!(encode(b"data", 0, 4).is_err());
+		assert!(encode(b"data", 100, 0).is_err());
+	}
+
+	#[test]
+	fn scans() {
+		let mut data = vec![0u8; 300];
+		ChaCha8Rng::seed_from_u64(2).fill(data.as_mut_slice());
+		let code = encode(&data, 100, 6).unwrap();
+
+		for (seed, (scale, angle, noise)) in [
+			(0.6, 0.0, 10.0),
+			(1.0, 2.5, 30.0),
+			(1.37, -4.0, 40.0),
+			(0.8, 1.0, 50.0),
+			(2.0, -1.5, 20.0),
+		]
+		.into_iter()
+		.enumerate()
+		{
+			let scanned = scan(&code, scale, angle, noise, seed as u64);
+			assert_eq!(decode(&scanned).map(|(data, _)| data), Ok(data.clone()));
+		}
+	}
+
+	#[test]
+	fn stains() {
+		let mut data = vec![0u8; 300];
+		ChaCha8Rng::seed_from_u64(3).fill(data.as_mut_slice());
+		let mut code = encode(&data, 100, 4).unwrap();
+
+		// A stain over a part of the data, away from the finder patterns
+		let width = code.width as usize;
+		for y in width / 3..width / 2 {
+			code.pixels[y * width + width / 3..y * width + width * 2 / 3].fill(0);
+		}
+		let (decoded, corrected) = decode(&code).unwrap();
+		assert_eq!(decoded, data);
+		assert!(corrected > 0);
+	}
+}
@@ -1,29 +1,88 @@
-#![feature(isqrt)]
-mod header;
-
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
 use std::env;
+use std::ffi::OsStr;
 use std::fs::File;
+use std::fs::Metadata;
 use std::io::BufWriter;
+use std::io::Write;
+use std::ops::Range;
+use std::path::Path;
 use std::process::ExitCode;
+use std::time::Duration;
+use std::time::SystemTime;
 
 use crc::Crc;
 use getopts::Matches;
 use getopts::Options;
-use header::Decode;
-use header::Encode;
-use header::Header;
 use png::BitDepth;
 use png::ColorType;
+use png_data::archive::Archive;
+use png_data::block;
+use png_data::carrier::add_payload;
+use png_data::carrier::is_stripped_carrier;
+use png_data::carrier::read_carrier;
+use png_data::compress::Codec;
+use png_data::compress::Compression;
+use png_data::crypt::encode_key;
+use png_data::crypt::generate_keys;
+use png_data::crypt::read_key;
+use png_data::crypt::HeaderCrypt;
+use png_data::crypt::RecipientCrypt;
+use png_data::crypt::PUBLIC_KEY_PREFIX;
+use png_data::crypt::SECRET_KEY_PREFIX;
+use png_data::embed::default_seed;
+use png_data::embed::derive_seed;
+use png_data::embed::EmbedAlgorithm;
+use png_data::fec::Fec;
+use png_data::fountain::Fountain;
+use png_data::header::civil_time;
+use png_data::header::decode_copy;
+use png_data::header::header_copies;
+use png_data::header::restored_mode;
+use png_data::header::Digest;
+use png_data::header::DigestAlgorithm;
+use png_data::header::Encode;
+use png_data::header::Format;
+use png_data::header::Header;
+use png_data::header::Meta;
+use png_data::header::Version;
+use png_data::header::COMMENT_KEY;
+use png_data::paper;
+use png_data::polyglot::append_zip;
+use png_data::rescan::rescan;
+use png_data::rescan::Image;
+use png_data::shape::bits_per_pixel;
+use png_data::shape::minimum_size;
+use png_data::shape::Layout;
+use png_data::shape::Padding;
+use png_data::shape::Shape;
+use png_data::shape::MAX_DIMENSION;
+use png_data::sign;
+use png_data::sign::fingerprint;
+use png_data::visualize::Coloring;
+use png_data::visualize::Visualization;
 use rand::Rng;
 use rand::SeedableRng;
 use rand_chacha::ChaCha8Rng;
 
 fn print_usage(program: &str, opts: Options) {
 	let brief = format!(
-		"Usage: {0} -(e|z|d) [FILE [-o OUTPUT]] [opts]
-		Encode: {0} -e file.tar -l rgb8 -o out.png -c \"(.tar)\"
+		"Usage: {0} -(e|z|d) [FILE [-o OUTPUT]] [PARTS..] [opts]
+		Encode: {0} -e file.tar -l rgb8 -o out.png -c \"(.tar)\" --meta mime=application/x-tar
 		Info:   {0} -z out.png # (.tar)
-		Decode: {0} -d out.png -o file.tar",
+		Decode: {0} -d out.png [-o file.tar]
+		Parts:  {0} -d out.001.png out.002.png -o file.tar
+		Animated: {0} -e file.tar -l rgb8 -o out.png --frames 10
+		Archive: {0} -e dir file.txt -l rgb8 -o out.png
+		List:   {0} --list out.png
+		Extract: {0} -d out.png -o dir [--extract dir/file.txt]
+		Visualize: {0} --visualize file.bin -o out.png [--color entropy] [--hilbert]
+		Carrier: {0} -e file.tar --carrier logo.png -o out.png
+		Zip:    {0} -e file.tar -l rgb8 -o out.png --zip # also opens with unzip
+		Paper:  {0} -e key.txt --paper -o key.png; {0} -d scan.png --paper -o key.txt
+		Upgrade: {0} --upgrade old.png -o new.png [--padding hash|zero|random]
+		Keygen: {0} --keygen key [--signing] # writes key and key.pub",
 		program
 	);
 	print!("{}", opts.usage(&brief));
@@ -41,7 +100,7 @@ There is NO WARRANTY, to the extent permitted by law."#
 	);
 }
 
-fn str_to_layout(layout: &str) -> Result<(ColorType, BitDepth), String> {
+fn str_to_layout(layout: &str) -> Result<Layout, String> {
 	let split = layout
 		.char_indices()
 		.find(|(_, c)| c.is_ascii_digit())
@@ -78,102 +137,295 @@ fn str_to_layout(layout: &str) -> Result<(ColorType, BitDepth), String> {
 				"Color type grayscale alpha cannot have bit depth: {bits}"
 			)),
 		},
+		("p", bits) => match bits {
+			"1" => Ok((ColorType::Indexed, BitDepth::One)),
+			"2" => Ok((ColorType::Indexed, BitDepth::Two)),
+			"4" => Ok((ColorType::Indexed, BitDepth::Four)),
+			"8" => Ok((ColorType::Indexed, BitDepth::Eight)),
+			_ => Err(format!("Color type indexed cannot have bit depth: {bits}")),
+		},
 		_ => Err(format!("Uknown layout: {layout}")),
 	}
 }
 
-fn bits_per_pixel(colors: ColorType, depth: BitDepth) -> u8 {
-	match colors {
-		ColorType::Rgb => depth as u8 * 3,
-		ColorType::Rgba => depth as u8 * 4,
-		ColorType::Grayscale => depth as u8,
-		ColorType::GrayscaleAlpha => depth as u8 * 2,
-		_ => panic!("Unsupported color type: {colors:#?}"),
-	}
-}
+/// Viridis colors the palette is interpolated from
+const VIRIDIS: [[u8; 3]; 5] = [
+	[0x44, 0x01, 0x54],
+	[0x3B, 0x52, 0x8B],
+	[0x21, 0x91, 0x8C],
+	[0x5E, 0xC9, 0x62],
+	[0xFD, 0xE7, 0x25],
+];
 
-fn best_layout(size: u64, bits_per_pixel: u8) -> (u32, u32) {
-	let sz = (size * 8).div_ceil(bits_per_pixel as u64);
-	let width = sz.isqrt();
-	(width as u32, sz.div_ceil(width) as u32)
+/// Generates the palette of indexed layouts, a viridis ramp with one entry per index.
+/// The indices are the stored bytes, the palette only changes how the image looks
+fn palette(depth: BitDepth) -> Vec<u8> {
+	let entries = 1usize << depth as u8;
+	(0..entries)
+		.flat_map(|index| {
+			let position = index * (VIRIDIS.len() - 1) * 256 / (entries - 1).max(1);
+			let (stop, fract) = (position / 256, position % 256);
+			let next = (stop + 1).min(VIRIDIS.len() - 1);
+			(0..3).map(move |channel| {
+				let (from, to) = (
+					VIRIDIS[stop][channel] as usize,
+					VIRIDIS[next][channel] as usize,
+				);
+				((from * (256 - fract) + to * fract) / 256) as u8
+			})
+		})
+		.collect()
 }
 
-/// Gets the minimum image buffer size in bytes
-fn minimum_size(color: ColorType, depth: BitDepth, width: u32, height: u32) -> usize {
-	let samples = width as usize * color.samples();
-	(match depth {
-		BitDepth::Sixteen => samples * 2,
-		BitDepth::Eight => samples,
-		subbyte => {
-			let samples_per_byte = 8 / subbyte as usize;
-			let whole = samples / samples_per_byte;
-			let fract = usize::from(samples % samples_per_byte > 0);
-			whole + fract
-		}
-	}) * height as usize
+/// Gets the shape from the `--width`, `--aspect` and `--canvas` options
+fn shape_from_matches(matches: &Matches, max_dimension: u32) -> Result<Shape, String> {
+	let dimension = |value: &str| {
+		value
+			.parse::<u32>()
+			.ok()
+			.filter(|value| (1..=max_dimension).contains(value))
+	};
+	let shapes = [
+			matches.opt_str("width").map(|width| {
+				dimension(width.as_str())
+					.map(Shape::Width)
+					.ok_or(format!(
+						"Invalid width `{width}`, expected 1 to {max_dimension}"
+					))
+			}),
+			matches.opt_str("aspect").map(|aspect| {
+				aspect
+					.split_once(':')
+					.and_then(|(w, h)| Some(Shape::Aspect(dimension(w)?, dimension(h)?)))
+					.ok_or(format!(
+						"Invalid aspect ratio `{aspect}`, expected WIDTH:HEIGHT, e.g 16:9"
+					))
+			}),
+			matches.opt_str("canvas").map(|canvas| {
+				canvas
+					.split_once('x')
+					.and_then(|(w, h)| Some(Shape::Canvas(dimension(w)?, dimension(h)?)))
+					.ok_or(format!(
+						"Invalid canvas `{canvas}`, expected WIDTHxHEIGHT of at most {max_dimension}x{max_dimension}, e.g 1080x1080"
+					))
+			}),
+		];
+	match shapes.into_iter().flatten().collect::<Vec<_>>().as_slice() {
+		[] => Ok(Shape::Square),
+		[shape] => shape.clone(),
+		_ => Err("Use only one of `--width`, `--aspect` and `--canvas`".into()),
+	}
 }
 
-fn encode(input: String, output: String, layout: String, matches: Matches) -> Result<(), String> {
-	let layout = str_to_layout(layout.as_str())?;
-	let comment = matches.opt_str("c");
-
-	// Input file data
-	let input_data = std::fs::read(&input)
-		.map_err(|err| format!("Failed to read input file `{input}`: {err}"))?;
-
-	// Header
-	let header = Header::new(header::Version::VERSION_1, input_data.as_slice(), comment)?;
-	let mut data = vec![];
-	header.encode(&mut data);
+/// Gets the padding from the `--padding` option, hash by default
+fn padding_from_matches(matches: &Matches) -> Result<Padding, String> {
+	matches
+		.opt_str("padding")
+		.map(|padding| padding.parse::<Padding>())
+		.transpose()
+		.map(|padding| padding.unwrap_or(Padding::Hash))
+}
 
+fn print_header(header: &Header, payload: Option<&[u8]>) {
 	eprintln!("=== HEADER ===");
 	eprintln!("Version: {:#?}", header.version);
-	eprintln!(
-		"Comment: {}",
-		header.comment.as_ref().map_or("", |c| c.as_str())
-	);
+	if header.version == Version::VERSION_1 {
+		eprintln!("Warning: VERSION_1 headers carry no signature or checksum");
+	}
+	if !header.metadata.is_empty() {
+		eprintln!("Metadata:");
+		for meta in &header.metadata {
+			eprintln!("  {meta}");
+		}
+	}
+	if let Some(digest) = &header.digest {
+		eprintln!("Digest: {digest}");
+	}
+	if let Some(name) = &header.file_name {
+		eprintln!("File: {name}");
+	}
+	if let Some(mode) = header.file_mode {
+		eprintln!("Mode: {mode:o}");
+	}
+	if let Some(mtime) = header.file_mtime {
+		eprintln!("Modified: {}", format_time(mtime));
+	}
+	if header.parts > 1 {
+		match header.set_id {
+			Some(set_id) => eprintln!(
+				"Part: {}/{} of set {set_id:016X}",
+				header.part + 1,
+				header.parts
+			),
+			None => eprintln!("Part: {}/{}", header.part + 1, header.parts),
+		}
+	}
 	eprintln!("Data: {}bytes CRC[{:X}]", header.data_len, header.data_crc);
+	if let Some(compression) = &header.compression {
+		eprintln!(
+			"Compression: {}, {}bytes uncompressed",
+			compression.codec, compression.original_len
+		);
+	}
+	if let Some(crypt) = &header.crypt {
+		eprintln!(
+			"Encryption: XChaCha20-Poly1305, Argon2id m={}KiB t={} p={}",
+			crypt.memory, crypt.iterations, crypt.parallelism
+		);
+	}
+	if let Some(recipients) = &header.recipients {
+		eprintln!(
+			"Encryption: XChaCha20-Poly1305, {} X25519 recipient(s)",
+			recipients.stanzas.len()
+		);
+	}
+	if let Some(fountain) = &header.fountain {
+		eprintln!(
+			"Fountain coding: RaptorQ, any {} of {} images",
+			fountain.needed, header.parts
+		);
+	}
+	if let Some(fec) = &header.fec {
+		eprintln!(
+			"Error correction: Reed-Solomon, {}% redundancy, {}bytes uncorrected",
+			fec.redundancy, fec.original_len
+		);
+	}
+	if let Some(archive) = &header.archive {
+		eprintln!(
+			"Archive: {} file(s), {}bytes",
+			archive.entries.len(),
+			archive.size()
+		);
+	}
+	if let Some(signature) = &header.signature {
+		let signer = fingerprint(&signature.public_key);
+		match payload.map(|payload| header.verify(payload, None)) {
+			Some(Ok(())) => eprintln!("Signed by: {signer}"),
+			Some(Err(_)) => eprintln!("Invalid signature by: {signer}"),
+			None => eprintln!("Unverified signature by: {signer}"),
+		}
+	}
 	eprintln!("==============");
+}
 
-	let bits_per_pixel = bits_per_pixel(layout.0, layout.1);
-	let (width, height) = best_layout((data.len() + input_data.len()) as u64, bits_per_pixel);
+fn format_time(time: SystemTime) -> String {
+	let [year, month, day, hours, minutes, seconds] = civil_time(time);
+	format!("{year:04}-{month:02}-{day:02} {hours:02}:{minutes:02}:{seconds:02} UTC")
+}
 
-	// Encode
-	let output_file = File::create(&output)
-		.map_err(|err| format!("Failed to open output file `{output}`: {err}"))?;
-	let w = &mut BufWriter::new(output_file);
-	let mut encoder = png::Encoder::new(w, width, height);
-	encoder.set_color(layout.0);
-	encoder.set_depth(layout.1);
-	encoder.set_compression(png::Compression::Best);
-	let mut writer = encoder
-		.write_header()
-		.map_err(|err| format!("Failed to write png header: {err}"))?;
+/// Gets the passphrase from `--passphrase-file`, or prompts for it
+fn passphrase(matches: &Matches, confirm: bool) -> Result<String, String> {
+	let passphrase = match matches.opt_str("passphrase-file") {
+		Some(path) => std::fs::read_to_string(&path)
+			.map_err(|err| format!("Failed to read passphrase file `{path}`: {err}"))?
+			.lines()
+			.next()
+			.unwrap_or_default()
+			.to_string(),
+		None => {
+			let passphrase = rpassword::prompt_password("Passphrase: ")
+				.map_err(|err| format!("Failed to read passphrase: {err}"))?;
+			if confirm
+				&& rpassword::prompt_password("Confirm passphrase: ")
+					.map_err(|err| format!("Failed to read passphrase: {err}"))?
+					!= passphrase
+			{
+				return Err("Passphrases do not match".into());
+			}
+			passphrase
+		}
+	};
+	if passphrase.is_empty() {
+		return Err("Empty passphrase".into());
+	}
+	Ok(passphrase)
+}
 
-	// Image byte length
-	let byte_len = minimum_size(layout.0, layout.1, width, height);
-	data.reserve(byte_len);
+/// Gets the header metadata from the `-c` and `--meta` options
+fn metadata(matches: &Matches) -> Result<Vec<Meta>, String> {
+	let mut metadata = matches
+		.opt_strs("m")
+		.iter()
+		.map(|meta| meta.parse::<Meta>())
+		.collect::<Result<Vec<_>, _>>()?;
+	if let Some(comment) = matches.opt_str("c") {
+		metadata.insert(0, Meta::text(COMMENT_KEY, comment));
+	}
+	Ok(metadata)
+}
 
-	data.extend_from_slice(input_data.as_slice());
+/// Stores the name, permissions and modification time of the input file in the header
+fn store_attributes(header: &mut Header, input: &str, metadata: &Metadata) {
+	header.file_name = Path::new(input)
+		.file_name()
+		.map(|name| name.to_string_lossy().into_owned());
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::PermissionsExt;
+		header.file_mode = Some(metadata.permissions().mode() & 0o7777);
+	}
+	// Clamped to `SOURCE_DATE_EPOCH`, so that reproducible builds give identical images
+	let epoch = std::env::var("SOURCE_DATE_EPOCH")
+		.ok()
+		.and_then(|epoch| epoch.parse::<u64>().ok())
+		.map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+	header.file_mtime = metadata
+		.modified()
+		.ok()
+		.map(|mtime| epoch.map_or(mtime, |epoch| mtime.min(epoch)));
+}
 
-	// Fill with random data
-	let mut rng = ChaCha8Rng::from_entropy();
-	while data.len() < byte_len {
-		data.push(rng.gen::<u8>())
+/// Restores the permissions and modification time stored in the header
+fn restore_attributes(header: &Header, output: &str, file: &File) -> Result<(), String> {
+	if let Some(mtime) = header.file_mtime {
+		file.set_modified(mtime)
+			.map_err(|err| format!("Failed to set modification time of `{output}`: {err}"))?;
 	}
+	#[cfg(unix)]
+	if let Some(mode) = header.file_mode {
+		use std::os::unix::fs::PermissionsExt;
+		let created = file
+			.metadata()
+			.map_err(|err| format!("Failed to read permissions of `{output}`: {err}"))?
+			.permissions()
+			.mode();
+		let mode = restored_mode(mode, created);
+		file.set_permissions(std::fs::Permissions::from_mode(mode))
+			.map_err(|err| format!("Failed to set permissions of `{output}`: {err}"))?;
+	}
+	Ok(())
+}
 
-	writer
-		.write_image_data(&data)
-		.map_err(|err| format!("Failed to write image data: {err}"))?;
-	println!("File written to `{output}`");
+/// Gets the output path from the file name stored in the header
+fn stored_output(header: &Header) -> Result<String, String> {
+	let name = header
+		.file_name
+		.as_ref()
+		.ok_or("Missing required output (-o|--output) option, the image holds no file name")?;
 
-	Ok(())
+	// Never write outside of the current directory
+	if Path::new(name).file_name() != Some(OsStr::new(name)) {
+		return Err(format!(
+			"Refusing to use stored file name `{name}`, use -o(--output) instead"
+		));
+	}
+	if Path::new(name).exists() {
+		return Err(format!(
+			"`{name}` already exists, use -o(--output) to overwrite it"
+		));
+	}
+
+	Ok(name.clone())
 }
 
-fn decode_header(input: String, _matches: Matches) -> Result<(), String> {
-	// Input file data
-	let decoder = png::Decoder::new(
-		File::open(&input).map_err(|err| format!("Failed to read input file `{input}`: {err}"))?,
+/// Reads the image data and layout of a png file, the data of each frame of animated images
+/// following the previous frame's
+fn read_image(input: &str) -> Result<(Vec<u8>, png::OutputInfo), String> {
+	// Payloads can be much larger than the decoder's default memory limit
+	let decoder = png::Decoder::new_with_limits(
+		File::open(input).map_err(|err| format!("Failed to read input file `{input}`: {err}"))?,
+		png::Limits { bytes: usize::MAX },
 	);
 	let mut reader = decoder
 		.read_info()
@@ -185,26 +437,325 @@ fn decode_header(input: String, _matches: Matches) -> Result<(), String> {
 
 	data.resize(info.buffer_size(), 0);
 
-	let mut it = data.iter().enumerate().map(|(idx, byte)| (idx, *byte));
-	let header =
-		Header::decode(&mut it).map_err(|err| format!("Failed to decode header: {err}"))?;
-	eprintln!("=== HEADER ===");
-	eprintln!("Version: {:#?}", header.version);
-	eprintln!(
-		"Comment: {}",
-		header.comment.as_ref().map_or("", |c| c.as_str())
+	// The default image may not be part of the animation
+	let frames = match reader.info().animation_control {
+		Some(animation) if reader.info().frame_control.is_some() => animation.num_frames - 1,
+		Some(animation) => animation.num_frames,
+		None => 0,
+	};
+	for frame in 0..frames {
+		let start = data.len();
+		data.resize(start + reader.output_buffer_size(), 0);
+		let frame_info = reader
+			.next_frame(&mut data[start..])
+			.map_err(|err| format!("Failed to read frame #{} of `{input}`: {err}", frame + 2))?;
+		data.truncate(start + frame_info.buffer_size());
+	}
+	Ok((data, info))
+}
+
+/// Gets the path of a part's image, e.g `out.png` -> `out.002.png`
+fn part_path(output: &str, part: u32) -> String {
+	let path = Path::new(output);
+	match (path.file_stem(), path.extension()) {
+		(Some(stem), Some(ext)) => path
+			.with_file_name(format!(
+				"{}.{:03}.{}",
+				stem.to_string_lossy(),
+				part + 1,
+				ext.to_string_lossy()
+			))
+			.to_string_lossy()
+			.into_owned(),
+		_ => format!("{output}.{:03}", part + 1),
+	}
+}
+
+/// Gets the output path from a part's image path, e.g `out.002.png` -> `out.png`
+fn part_base(path: &str) -> Option<String> {
+	let path = Path::new(path);
+	let (base, part) = path.file_stem()?.to_str()?.rsplit_once('.')?;
+	if part.len() < 3 || !part.bytes().all(|c| c.is_ascii_digit()) {
+		return None;
+	}
+	let ext = path.extension()?.to_str()?;
+	Some(
+		path.with_file_name(format!("{base}.{ext}"))
+			.to_string_lossy()
+			.into_owned(),
+	)
+}
+
+/// Gets an upper bound of the size of an image holding `len` bytes of incompressible data
+fn png_size(layout: Layout, shape: Shape, frames: u32, len: u64) -> u64 {
+	let (width, height) = shape.layout(
+		len.div_ceil(frames as u64),
+		bits_per_pixel(layout.0, layout.1),
 	);
-	eprintln!("Data: {}bytes CRC[{:X}]", header.data_len, header.data_crc);
-	eprintln!("==============");
+	// A filter byte per row, stored deflate blocks of at least 16KiB
+	let filtered =
+		(minimum_size(layout.0, layout.1, width, height) as u64 + height as u64) * frames as u64;
+	// The png chunks, a palette of up to 256 colors and the animation chunks of each frame
+	filtered + filtered.div_ceil(16384) * 5 + 1024 + 64 * frames as u64
+}
+
+/// Lists the existing part images of an output path, e.g `out.png` -> `out.001.png`...
+fn part_files(output: &str) -> Vec<String> {
+	let dir = match Path::new(output).parent() {
+		Some(dir) if !dir.as_os_str().is_empty() => dir,
+		_ => Path::new("."),
+	};
+	std::fs::read_dir(dir).map_or(vec![], |entries| {
+		entries
+			.flatten()
+			.map(|entry| {
+				Path::new(output)
+					.with_file_name(entry.file_name())
+					.to_string_lossy()
+					.into_owned()
+			})
+			.filter(|path| part_base(path).as_deref() == Some(output))
+			.collect()
+	})
+}
+
+/// Gets the image data available to each part
+fn part_capacity(
+	layout: Layout,
+	shape: Shape,
+	frames: u32,
+	max_dimension: u32,
+	max_bytes: Option<u64>,
+) -> usize {
+	// Whole pixels, so that the layout never exceeds the maximum dimension
+	let bits_per_pixel = bits_per_pixel(layout.0, layout.1);
+	let capacity =
+		shape.max_pixels(max_dimension, bits_per_pixel) * bits_per_pixel as u64 / 8 * frames as u64;
+	let Some(max_bytes) = max_bytes else {
+		return capacity as usize;
+	};
+	// Largest data whose image fits, the image size growing with the data
+	let (mut low, mut high) = (0, capacity);
+	while low < high {
+		let len = (low + high).div_ceil(2);
+		if png_size(layout, shape, frames, len) <= max_bytes {
+			low = len;
+		} else {
+			high = len - 1;
+		}
+	}
+	low as usize
+}
+
+/// Finds the `lo` bits of a png_embed image using the default seed
+fn detect_embed(data: &[u8], info: &png::OutputInfo) -> Option<u8> {
+	let seed = derive_seed(default_seed(info.width, info.height).as_str()).ok()?;
+	(1..=7).find(|bits| {
+		block::read_header(&EmbedAlgorithm::Lo(*bits), data, seed)
+			.is_ok_and(|(header, _)| header.format == Some(Format::Embed))
+	})
+}
+
+/// Decodes the first valid header copy of the image data, or the bitwise majority of the copies
+/// when they are all damaged
+///
+/// Returns the header and the ranges of the image data holding the payload
+fn read_header(input: &str, data: &[u8]) -> Result<(Header, Vec<Range<usize>>), String> {
+	let len = data.len();
+	let mut errors = vec![];
+	for copy in 0..4 {
+		let decoded = match copy {
+			0 => decode_copy(data.iter().copied()),
+			1 => decode_copy(data[len / 2..].iter().copied()),
+			2 => decode_copy(data.iter().rev().copied()),
+			_ => decode_copy((0..len / 4).map(|i| {
+				let (a, b, c) = (data[i], data[len / 2 + i], data[len - 1 - i]);
+				(a & b) | (a & c) | (b & c)
+			})),
+		};
+		let (header, header_len) = match decoded {
+			Ok(decoded) => decoded,
+			Err(err) => {
+				errors.push(err);
+				continue;
+			}
+		};
+		header
+			.check_format(Format::Data)
+			.map_err(|err| format!("Failed to read `{input}`: {err}"))?;
+
+		let ranges = if header.version == Version::VERSION_1 {
+			// VERSION_1 images only hold the first copy
+			if copy != 0 {
+				continue;
+			}
+			[header_len..len, len..len]
+		} else {
+			match copy {
+				0 => {}
+				3 => eprintln!("Warning: damaged headers in `{input}`, using their majority"),
+				_ => eprintln!(
+					"Warning: damaged header in `{input}`, using copy #{}",
+					copy + 1
+				),
+			}
+			if len < header_len * 4 {
+				return Err(format!(
+					"Image `{input}` is too small to hold {header_len}bytes headers, this is not a png_data image"
+				));
+			}
+			let [start, middle, end] = header_copies(len, header_len);
+			[start.end..middle.start, middle.end..end.start]
+		};
+
+		// Keep only the payload
+		let mut remaining = header.data_len;
+		let ranges = ranges
+			.into_iter()
+			.map(|range| {
+				let range_len = remaining.min(range.len() as u64);
+				remaining -= range_len;
+				range.start..range.start + range_len as usize
+			})
+			.collect::<Vec<_>>();
+		if remaining != 0 {
+			return Err(format!(
+				"Header for `{input}` claims {}bytes of data but the image only holds {}bytes, this is not a png_data image",
+				header.data_len,
+				header.data_len - remaining
+			));
+		}
+
+		// Without a header checksum, only the data CRC can tell a VERSION_1 image apart
+		if header.version == Version::VERSION_1 {
+			let file_data = &data[ranges[0].clone()];
+			if Crc::<u32>::new(&crc::CRC_32_CKSUM).checksum(file_data) != header.data_crc {
+				return Err(format!(
+					"Legacy header for `{input}` does not match its data, this is not a png_data image"
+				));
+			}
+		}
+
+		return Ok((header, ranges));
+	}
+
+	Err(format!(
+		"Failed to decode header for `{input}`: {}, this is not a png_data image",
+		errors[0]
+	))
+}
+
+/// Reads an image and its header, reading the original pixels of scaled up or framed images
+fn read_image_header(input: &str) -> Result<(Image, Header, Vec<Range<usize>>), String> {
+	let (data, info) = read_image(input)?;
+	let err = match read_header(input, data.as_slice()) {
+		Ok((header, ranges)) => return Ok(((data, info), header, ranges)),
+		Err(err) => err,
+	};
+
+	// Embeds are hidden in the low bits of the image
+	if let Some(bits) = detect_embed(data.as_slice(), &info) {
+		return Err(format!(
+			"`{input}` is a png_embed image, read it with `png_embed -l lo{bits}`"
+		));
+	}
+	let Some((data, info)) = rescan(input, data.as_slice(), &info) else {
+		if is_stripped_carrier(input) {
+			eprintln!(
+				"Warning: `{input}` was written as a carrier image but has no `pdTa` chunk, re-saving the image stripped it"
+			);
+		}
+		return Err(err);
+	};
+	let (header, ranges) = read_header(input, data.as_slice())?;
+	Ok(((data, info), header, ranges))
+}
+
+/// Writes a copy of a carrier image holding a header and its payload
+fn write_carrier(
+	output: &str,
+	carrier: &str,
+	header: &Header,
+	payload: &[u8],
+) -> Result<(), String> {
+	// The carrier must be a readable png
+	read_image(carrier)?;
+	let image = add_payload(carrier, header, payload)?;
+	print_header(header, Some(payload));
+
+	std::fs::write(output, image)
+		.map_err(|err| format!("Failed to write output file `{output}`: {err}"))?;
+	println!("File written to `{output}`");
 
 	Ok(())
 }
 
-fn decode(input: String, output: String, _matches: Matches) -> Result<(), String> {
-	// Input file data
-	let decoder = png::Decoder::new(
-		File::open(&input).map_err(|err| format!("Failed to read input file `{input}`: {err}"))?,
+/// Reads the header of a carrier image's chunks, or of an image's pixels
+fn read_any_header(input: &str) -> Result<Header, String> {
+	match read_carrier(input)? {
+		Some((header, _)) => Ok(header),
+		None => read_image_header(input).map(|(_, header, _)| header),
+	}
+}
+
+/// Layout, number of frames and size of an image's pixels
+type Frames = (Layout, u32, (u32, u32));
+
+/// Reads the header and the CRC-checked payload of an image, from its chunks or its pixels.
+///
+/// Returns the layout, number of frames and size of the image's pixels, `None` for carrier images
+fn read_payload(input: &str) -> Result<(Header, Vec<u8>, Option<Frames>), String> {
+	let (header, mut payload, layout) = match read_carrier(input)? {
+		Some((header, payload)) => (header, payload, None),
+		None => {
+			let ((data, info), header, ranges) = read_image_header(input)?;
+			let mut payload = Vec::with_capacity(header.data_len as usize);
+			for range in ranges {
+				payload.extend_from_slice(&data[range]);
+			}
+			let frames = (data.len() / info.buffer_size()) as u32;
+			(
+				header,
+				payload,
+				Some((
+					(info.color_type, info.bit_depth),
+					frames,
+					(info.width, info.height),
+				)),
+			)
+		}
+	};
+
+	// Check crc
+	if let Some(fec) = &header.fec {
+		let corrected = fec
+			.repair(payload.as_mut_slice())
+			.map_err(|err| format!("Failed to repair `{input}`: {err}"))?;
+		if corrected != 0 {
+			eprintln!("Corrected {corrected} symbol(s) in `{input}`");
+		}
+	}
+	let crc = Crc::<u32>::new(&crc::CRC_32_CKSUM).checksum(payload.as_slice());
+	if crc != header.data_crc {
+		Err(format!(
+			"Data CRC[{crc:X}] does not match header CRC[{:X}] in `{input}`",
+			header.data_crc
+		))?;
+	}
+
+	Ok((header, payload, layout))
+}
+
+/// Default redundancy of printed codes, in percent
+const PAPER_REDUNDANCY: u8 = 100;
+
+/// Reads the header and the CRC-checked payload of a printed code from a scan of any layout
+fn read_paper(input: &str) -> Result<(Header, Vec<u8>), String> {
+	let mut decoder = png::Decoder::new_with_limits(
+		File::open(input).map_err(|err| format!("Failed to read input file `{input}`: {err}"))?,
+		png::Limits { bytes: usize::MAX },
 	);
+	decoder.set_transformations(png::Transformations::normalize_to_color8());
 	let mut reader = decoder
 		.read_info()
 		.map_err(|err| format!("Failed to read png info for `{input}`: {err}"))?;
@@ -213,51 +764,1102 @@ fn decode(input: String, output: String, _matches: Matches) -> Result<(), String
 		.next_frame(data.as_mut_slice())
 		.map_err(|err| format!("Failed to read png info for `{input}`: {err}"))?;
 
-	data.resize(info.buffer_size(), 0);
-
-	let mut it = data.iter().enumerate().map(|(idx, byte)| (idx, *byte));
-	let header = {
-		//let mut temp_it = std::mem::take(&mut it);
-		Header::decode(&mut it).map_err(|err| format!("Failed to decode header: {err}"))?
-	};
-	eprintln!("=== HEADER ===");
-	eprintln!("Version: {:#?}", header.version);
-	eprintln!(
-		"Comment: {}",
-		header.comment.as_ref().map_or("", |c| c.as_str())
-	);
-	eprintln!("Data: {}bytes CRC[{:X}]", header.data_len, header.data_crc);
-	eprintln!("==============");
+	// Luminance over a white background
+	let pixels = data[..info.buffer_size()]
+		.chunks_exact(info.color_type.samples())
+		.map(|pixel| {
+			let (luma, alpha) = match *pixel {
+				[r, g, b, a] => ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000, a),
+				[r, g, b] => (
+					(r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000,
+					255,
+				),
+				[l, a] => (l as u32, a),
+				_ => (pixel[0] as u32, 255),
+			};
+			((luma * alpha as u32 + 255 * (255 - alpha as u32)) / 255) as u8
+		})
+		.collect();
+	let (data, corrected) = paper::decode(&paper::Bitmap {
+		width: info.width,
+		height: info.height,
+		pixels,
+	})
+	.map_err(|err| format!("Failed to read `{input}`: {err}"))?;
+	if corrected != 0 {
+		eprintln!("Corrected {corrected} symbol(s) in `{input}`");
+	}
 
-	// Check crc
-	let data_start = it.next().ok_or("Failed to get data start".to_string())?.0;
-	let file_data = &data[data_start..data_start + header.data_len as usize];
-	let crc = Crc::<u32>::new(&crc::CRC_32_CKSUM).checksum(file_data);
-	if crc != header.data_crc {
+	let (header, header_len) = decode_copy(data.iter().copied())?;
+	header
+		.check_format(Format::Data)
+		.map_err(|err| format!("Failed to read `{input}`: {err}"))?;
+	let payload = data[header_len..].to_vec();
+	let crc = Crc::<u32>::new(&crc::CRC_32_CKSUM).checksum(payload.as_slice());
+	if payload.len() as u64 != header.data_len || crc != header.data_crc {
 		Err(format!(
-			"Data CRC[{crc:X}] does not match header CRC[{:X}]",
+			"Data CRC[{crc:X}] does not match header CRC[{:X}] in `{input}`",
 			header.data_crc
 		))?;
 	}
+	Ok((header, payload))
+}
+
+/// Writes a header and its payload as a printable code
+fn write_paper(
+	output: &str,
+	header: &Header,
+	payload: &[u8],
+	redundancy: u8,
+	module: u32,
+) -> Result<(), String> {
+	let mut data = vec![];
+	header.encode(&mut data);
+	data.extend_from_slice(payload);
+	print_header(header, Some(payload));
+
+	let code = paper::encode(data.as_slice(), redundancy, module)?;
+	let output_file = File::create(output)
+		.map_err(|err| format!("Failed to open output file `{output}`: {err}"))?;
+	let mut encoder = png::Encoder::new(BufWriter::new(output_file), code.width, code.height);
+	encoder.set_color(ColorType::Grayscale);
+	encoder.set_depth(BitDepth::Eight);
+	encoder.set_compression(png::Compression::Best);
+	encoder
+		.write_header()
+		.and_then(|mut writer| writer.write_image_data(code.pixels.as_slice()))
+		.map_err(|err| format!("Failed to write image `{output}`: {err}"))?;
+	println!("File written to `{output}`");
+
+	Ok(())
+}
+
+/// Delay between the frames of animated images, in seconds
+const FRAME_DELAY: (u16, u16) = (1, 10);
+
+/// Gets the bytes of each frame holding a header and its payload. The middle and end copies must
+/// not overlap, and the first frame holds the first copy
+fn frame_size(header_len: usize, payload_len: usize, frames: u32) -> usize {
+	(header_len * 3 + payload_len)
+		.max(header_len * 4)
+		.div_ceil(frames as usize)
+		.max(header_len)
+}
+
+/// Encodes a header and its payload to a png image, animated over `frames` frames of equal size
+fn encode_image(
+	layout: Layout,
+	shape: Shape,
+	frames: u32,
+	padding: Padding,
+	header: &Header,
+	payload: &[u8],
+) -> Result<Vec<u8>, String> {
+	let mut header_data = vec![];
+	header.encode(&mut header_data);
+	let header_len = header_data.len();
+
+	print_header(header, Some(payload));
+
+	let bits_per_pixel = bits_per_pixel(layout.0, layout.1);
+	let (width, height) = shape.layout(
+		frame_size(header_len, payload.len(), frames) as u64,
+		bits_per_pixel,
+	);
+
+	// Encode
+	let mut image = vec![];
+	let mut encoder = png::Encoder::new(&mut image, width, height);
+	encoder.set_color(layout.0);
+	encoder.set_depth(layout.1);
+	if layout.0 == ColorType::Indexed {
+		encoder.set_palette(palette(layout.1));
+	}
+	encoder.set_compression(png::Compression::Best);
+	if frames > 1 {
+		encoder
+			.set_animated(frames, 0)
+			.and_then(|_| encoder.set_frame_delay(FRAME_DELAY.0, FRAME_DELAY.1))
+			.map_err(|err| format!("Failed to write png header: {err}"))?;
+	}
+	let mut writer = encoder
+		.write_header()
+		.map_err(|err| format!("Failed to write png header: {err}"))?;
+
+	// Fill with the padding
+	let frame_len = minimum_size(layout.0, layout.1, width, height);
+	let byte_len = frame_len * frames as usize;
+	let mut data = vec![0; byte_len];
+	match padding {
+		Padding::Hash => {
+			let seed = blake3::Hasher::new()
+				.update(&header_data)
+				.update(payload)
+				.finalize();
+			ChaCha8Rng::from_seed(*seed.as_bytes()).fill(data.as_mut_slice());
+		}
+		Padding::Zero => {}
+		Padding::Random => ChaCha8Rng::from_entropy().fill(data.as_mut_slice()),
+	}
+
+	// Header copies, with the payload around them
+	let [start, middle, end] = header_copies(byte_len, header_len);
+	data[start.clone()].copy_from_slice(&header_data);
+	data[middle.clone()].copy_from_slice(&header_data);
+	header_data.reverse();
+	data[end.clone()].copy_from_slice(&header_data);
+	let mut payload = payload;
+	for range in [start.end..middle.start, middle.end..end.start] {
+		let len = payload.len().min(range.len());
+		data[range.start..range.start + len].copy_from_slice(&payload[..len]);
+		payload = &payload[len..];
+	}
 
-	std::fs::write(&output, file_data)
+	for frame in data.chunks(frame_len) {
+		writer
+			.write_image_data(frame)
+			.map_err(|err| format!("Failed to write image data: {err}"))?;
+	}
+	writer
+		.finish()
+		.map_err(|err| format!("Failed to write image data: {err}"))?;
+
+	Ok(image)
+}
+
+fn encode(
+	input: String,
+	output: String,
+	layout: Option<String>,
+	matches: Matches,
+) -> Result<(), String> {
+	let metadata = metadata(&matches)?;
+
+	// Input file data, several files or a directory are stored as an archive
+	let inputs = std::iter::once(input.clone())
+		.chain(matches.free.iter().cloned())
+		.collect::<Vec<_>>();
+	let (input_data, file_metadata, archive) = if inputs.len() == 1 && !Path::new(&input).is_dir() {
+		let input_data = std::fs::read(&input)
+			.map_err(|err| format!("Failed to read input file `{input}`: {err}"))?;
+		let file_metadata = std::fs::metadata(&input)
+			.map_err(|err| format!("Failed to read input file `{input}` metadata: {err}"))?;
+		(input_data, Some(file_metadata), None)
+	} else {
+		let (input_data, archive) = Archive::build(inputs.as_slice())?;
+		(input_data, None, Some(archive))
+	};
+	// Polyglot images carry a zip of the files as they were read
+	let zip_data = matches.opt_present("zip").then(|| input_data.clone());
+	if zip_data.is_some() && (matches.opt_present("encrypt") || !matches.opt_strs("r").is_empty()) {
+		return Err("`--zip` stores the files in clear, it can not be used with encryption".into());
+	}
+	let digest = matches
+		.opt_str("digest")
+		.map(|algorithm| algorithm.parse::<DigestAlgorithm>())
+		.transpose()?
+		.map(|algorithm| Digest::new(algorithm, [input_data.as_slice()]));
+
+	// Compress the whole file before splitting it
+	let (input_data, compression) = match matches.opt_str("compress") {
+		Some(codec) => {
+			let (data, compression) = Compression::compress(codec.parse::<Codec>()?, &input_data)?;
+			(data, Some(compression))
+		}
+		None => (input_data, None),
+	};
+
+	// Header fields known before encryption, authenticated along with the encrypted payload
+	let mut template = Header::new(Format::Data, Version::CURRENT, &[], metadata)?;
+	template.digest = digest.clone();
+	template.compression = compression;
+	template.archive = archive;
+	if let Some(file_metadata) = file_metadata
+		.as_ref()
+		.filter(|_| !matches.opt_present("no-attributes"))
+	{
+		store_attributes(&mut template, input.as_str(), file_metadata);
+	}
+	let recipients = matches
+		.opt_strs("r")
+		.iter()
+		.map(|path| read_key(path, PUBLIC_KEY_PREFIX))
+		.collect::<Result<Vec<_>, _>>()?;
+	if !recipients.is_empty() && matches.opt_present("encrypt") {
+		return Err("Use either `--encrypt` or `-r(--recipient)`".into());
+	}
+	let (input_data, recipients) = if recipients.is_empty() {
+		(input_data, None)
+	} else {
+		if digest.is_some() {
+			eprintln!("Warning: the digest of the unencrypted file is stored in clear");
+		}
+		let (data, recipients) =
+			RecipientCrypt::encrypt(recipients.as_slice(), &input_data, |recipients| {
+				Header {
+					recipients: Some(recipients.clone()),
+					..template.clone()
+				}
+				.associated_data()
+			})?;
+		(data, Some(recipients))
+	};
+	template.recipients = recipients;
+	let (input_data, crypt) = if matches.opt_present("encrypt") {
+		if digest.is_some() {
+			eprintln!("Warning: the digest of the unencrypted file is stored in clear");
+		}
+		let (data, crypt) =
+			HeaderCrypt::encrypt(passphrase(&matches, true)?.as_str(), &input_data, |crypt| {
+				Header {
+					crypt: Some(crypt.clone()),
+					..template.clone()
+				}
+				.associated_data()
+			})?;
+		(data, Some(crypt))
+	} else {
+		(input_data, None)
+	};
+	template.crypt = crypt;
+
+	let redundancy = matches
+		.opt_str("fec")
+		.map(|redundancy| {
+			redundancy
+				.parse::<u8>()
+				.map_err(|err| format!("Invalid redundancy `{redundancy}`: {err}"))
+		})
+		.transpose()?;
+	let fountain = matches
+		.opt_str("fountain")
+		.map(|spec| {
+			spec.split_once('/')
+				.and_then(|(needed, images)| Some((needed.parse().ok()?, images.parse().ok()?)))
+				.ok_or(format!(
+					"Invalid fountain coding `{spec}`, expected NEEDED/IMAGES, e.g 3/5"
+				))
+		})
+		.transpose()?;
+	let sign_key = matches
+		.opt_str("sign")
+		.map(|path| read_key(path.as_str(), sign::SECRET_KEY_PREFIX))
+		.transpose()?;
+
+	let new_header = |payload: &[u8]| -> Result<Header, String> {
+		let header = Header::new(Format::Data, Version::CURRENT, payload, vec![])?;
+		Ok(Header {
+			data_len: header.data_len,
+			data_crc: header.data_crc,
+			..template.clone()
+		})
+	};
+
+	if matches.opt_present("paper") && matches.opt_present("carrier") {
+		return Err("Use either `--paper` or `--carrier`".into());
+	}
+	if matches.opt_present("paper") || matches.opt_present("carrier") {
+		if let Some(option) = ["frames", "width", "aspect", "canvas", "padding"]
+			.into_iter()
+			.find(|option| matches.opt_present(option))
+		{
+			return Err(format!("`--{option}` only applies to regular images"));
+		}
+	}
+	let write_zip = || -> Result<(), String> {
+		if let Some(data) = &zip_data {
+			let image = std::fs::read(&output)
+				.map_err(|err| format!("Failed to read output file `{output}`: {err}"))?;
+			let image = append_zip(image, input.as_str(), &new_header(&[])?, data)?;
+			std::fs::write(&output, image)
+				.map_err(|err| format!("Failed to write output file `{output}`: {err}"))?;
+			println!("Zip appended to `{output}`");
+		}
+		Ok(())
+	};
+
+	// Carrier images hold the header and payload in their chunks
+	if let Some(carrier) = matches.opt_str("carrier") {
+		if fountain.is_some()
+			|| matches.opt_present("max-bytes")
+			|| matches.opt_present("max-dimension")
+		{
+			return Err("Carrier images can not be split over several images".into());
+		}
+		let (payload, fec) = match redundancy {
+			Some(redundancy) => {
+				let (payload, fec) = Fec::encode(redundancy, &input_data)?;
+				(payload, Some(fec))
+			}
+			None => (input_data, None),
+		};
+		let mut header = new_header(&payload)?;
+		header.fec = fec;
+		if let Some(key) = &sign_key {
+			header.sign(key, &payload)?;
+		}
+		write_carrier(output.as_str(), carrier.as_str(), &header, &payload)?;
+		return write_zip();
+	}
+
+	// Printed codes hold the header and payload together, with their own error correction
+	if matches.opt_present("paper") {
+		if fountain.is_some()
+			|| matches.opt_present("max-bytes")
+			|| matches.opt_present("max-dimension")
+		{
+			return Err("Printed codes can not be split over several images".into());
+		}
+		let module = matches
+			.opt_str("module")
+			.map(|module| {
+				module
+					.parse::<u32>()
+					.map_err(|err| format!("Invalid module size `{module}`: {err}"))
+			})
+			.transpose()?
+			.unwrap_or(8);
+		let mut header = new_header(&input_data)?;
+		if let Some(key) = &sign_key {
+			header.sign(key, &input_data)?;
+		}
+		write_paper(
+			output.as_str(),
+			&header,
+			&input_data,
+			redundancy.unwrap_or(PAPER_REDUNDANCY),
+			module,
+		)?;
+		return write_zip();
+	}
+	let layout = str_to_layout(
+		layout
+			.ok_or("Missing required png layout (-l|--layout) option")?
+			.as_str(),
+	)?;
+	let frames = matches
+		.opt_str("frames")
+		.map(|frames| {
+			frames
+				.parse::<u32>()
+				.ok()
+				.filter(|frames| *frames > 0)
+				.ok_or(format!("Invalid number of frames `{frames}`"))
+		})
+		.transpose()?
+		.unwrap_or(1);
+
+	// Split the payload when it does not fit in a single image
+	let mut header_data = vec![];
+	let mut header = new_header(&[])?;
+	header.set_id = Some(0);
+	header.fountain = fountain.map(|(needed, _)| Fountain {
+		config: [0; 12],
+		needed,
+	});
+	header.fec = redundancy.map(|redundancy| Fec {
+		redundancy,
+		original_len: 0,
+	});
+	if let Some(key) = &sign_key {
+		header.sign(key, &[])?;
+	}
+	header.encode(&mut header_data);
+	let max_dimension = matches
+		.opt_str("max-dimension")
+		.map(|max| {
+			max.parse::<u32>()
+				.ok()
+				.filter(|max| (1..=MAX_DIMENSION).contains(max))
+				.ok_or(format!(
+					"Invalid maximum dimension `{max}`, expected 1 to {MAX_DIMENSION}"
+				))
+		})
+		.transpose()?
+		.unwrap_or(MAX_DIMENSION);
+	let max_bytes = matches
+		.opt_str("max-bytes")
+		.map(|max| {
+			max.parse::<u64>()
+				.map_err(|err| format!("Invalid maximum size `{max}`: {err}"))
+		})
+		.transpose()?;
+	let shape = shape_from_matches(&matches, max_dimension)?;
+	shape.check(bits_per_pixel(layout.0, layout.1))?;
+	let padding = padding_from_matches(&matches)?;
+	let part_len = part_capacity(layout, shape, frames, max_dimension, max_bytes)
+		.checked_sub(header_data.len() * 3)
+		.map(|len| match redundancy {
+			Some(redundancy) => Fec::capacity(redundancy, len),
+			None => Ok(len),
+		})
+		.transpose()?
+		.filter(|len| *len > 0)
+		.ok_or("Header does not fit in a single image, raise the size limits".to_string())?;
+	let (parts, fountain) = match fountain {
+		Some((needed, images)) => {
+			let (images, fountain) = Fountain::encode(&input_data, needed, images)?;
+			if let Some(image) = images.iter().find(|image| image.len() > part_len) {
+				return Err(format!(
+					"Fountain coded images need {}bytes but only {part_len}bytes fit in an image, raise the number of images needed",
+					image.len()
+				));
+			}
+			(images, Some(fountain))
+		}
+		None if input_data.is_empty() => (vec![vec![]], None),
+		None => (
+			input_data.chunks(part_len).map(<[u8]>::to_vec).collect(),
+			None,
+		),
+	};
+	let parts_num = u32::try_from(parts.len())
+		.map_err(|_| format!("Too many parts required: {}", parts.len()))?;
+	// Only split images of a given shape when asked to
+	if parts_num > 1 && fountain.is_none() && max_bytes.is_none() {
+		let image = match shape {
+			Shape::Square => None,
+			Shape::Width(width) => Some(format!("an image {width} pixels wide")),
+			Shape::Aspect(w, h) => Some(format!("a {w}:{h} image")),
+			Shape::Canvas(width, height) => Some(format!("a {width}x{height} canvas")),
+		};
+		if let Some(image) = image {
+			return Err(format!(
+				"The file needs {}bytes but {image} holds {part_len}bytes, use a larger image, more `--frames` or `--max-bytes` to split it",
+				input_data.len()
+			));
+		}
+	}
+	if zip_data.is_some() && parts_num > 1 {
+		return Err(format!(
+			"`--zip` needs a single image but the file is split in {parts_num} images"
+		));
+	}
+	// Parts of a file split the same way share their set identifier
+	let set_id = {
+		let hash = blake3::Hasher::new()
+			.update(&input_data)
+			.update(&(part_len as u64).to_le_bytes())
+			.update(&parts_num.to_le_bytes())
+			.finalize();
+		u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap())
+	};
+
+	// Every part is checked against the size limit before any is written
+	let mut images = vec![];
+	for (part, payload) in parts.into_iter().enumerate() {
+		// Error correction is local to each image
+		let (payload, fec) = match redundancy {
+			Some(redundancy) => {
+				let (payload, fec) = Fec::encode(redundancy, &payload)?;
+				(payload, Some(fec))
+			}
+			None => (payload, None),
+		};
+		let payload = payload.as_slice();
+		let mut header = new_header(payload)?;
+		header.part = part as u32;
+		header.parts = parts_num;
+		header.set_id = (parts_num > 1).then_some(set_id);
+		header.fountain = fountain.clone();
+		header.fec = fec;
+		if let Some(key) = &sign_key {
+			header.sign(key, payload)?;
+		}
+
+		let output = if parts_num == 1 {
+			output.clone()
+		} else {
+			part_path(output.as_str(), part as u32)
+		};
+		let image = encode_image(layout, shape, frames, padding, &header, payload)?;
+		if let Some(max_bytes) = max_bytes {
+			if image.len() as u64 > max_bytes {
+				return Err(format!(
+					"`{output}` would be {}bytes, over the {max_bytes}bytes limit, nothing was written",
+					image.len()
+				));
+			}
+		}
+		images.push((output, image));
+	}
+	for (output, image) in images {
+		std::fs::write(&output, image)
+			.map_err(|err| format!("Failed to write output file `{output}`: {err}"))?;
+		println!("File written to `{output}`");
+	}
+
+	write_zip()
+}
+
+fn decode_header(input: String, matches: Matches) -> Result<(), String> {
+	if matches.opt_present("paper") {
+		let (header, payload) = read_paper(input.as_str())?;
+		print_header(&header, Some(payload.as_slice()));
+		return Ok(());
+	}
+
+	// The payload is needed to verify the signature, damaged payloads only show the header
+	let (header, payload) = match read_payload(input.as_str()) {
+		Ok((header, payload, _)) => (header, Some(payload)),
+		Err(_) => (read_any_header(input.as_str())?, None),
+	};
+	print_header(&header, payload.as_deref());
+
+	Ok(())
+}
+
+/// Number of missing parts named when decoding a split payload fails
+const MAX_MISSING: usize = 16;
+
+fn decode(input: String, output: Option<String>, matches: Matches) -> Result<(), String> {
+	// Split payloads list their remaining parts after the first one, in any order
+	let first_input = input.clone();
+	let mut inputs = std::iter::once(input)
+		.chain(matches.free.iter().cloned())
+		.collect::<VecDeque<_>>();
+	let mut searched = false;
+
+	let verify_key = matches
+		.opt_str("verify")
+		.map(|path| read_key(path.as_str(), sign::PUBLIC_KEY_PREFIX))
+		.transpose()?;
+
+	let mut first_header: Option<Header> = None;
+	// The part count comes from the images, only the parts read are stored
+	let mut parts = BTreeMap::new();
+	while let Some(input) = inputs.pop_front() {
+		let (header, file_data) = match matches.opt_present("paper") {
+			true => read_paper(input.as_str())?,
+			false => read_payload(input.as_str()).map(|(header, data, _)| (header, data))?,
+		};
+		print_header(&header, Some(file_data.as_slice()));
+		header.verify(file_data.as_slice(), verify_key.as_ref())?;
+		let file_data = match &header.fec {
+			Some(fec) => fec.decode(file_data.as_slice())?,
+			None => file_data,
+		};
+
+		if let Some(first) = &first_header {
+			if header.parts != first.parts {
+				Err(format!(
+					"`{input}` is part of a {}-part payload, expected {} parts",
+					header.parts, first.parts
+				))?;
+			}
+			if header.set_id != first.set_id {
+				Err(format!(
+					"`{input}` belongs to another set of images than `{first_input}`"
+				))?;
+			}
+		}
+		if parts.insert(header.part, file_data).is_some() {
+			Err(format!("Part {} was given more than once", header.part + 1))?;
+		}
+		first_header.get_or_insert(header);
+
+		// Look for the missing parts next to the first one, among the existing files
+		if inputs.is_empty() && !searched {
+			searched = true;
+			if let Some(base) = part_base(first_input.as_str()) {
+				let read = parts
+					.keys()
+					.map(|part| part_path(base.as_str(), *part))
+					.collect::<Vec<_>>();
+				inputs.extend(
+					part_files(base.as_str())
+						.into_iter()
+						.filter(|path| !read.contains(path)),
+				);
+			}
+		}
+	}
+	let header = first_header.unwrap();
+
+	let mut parts = match &header.fountain {
+		// Any sufficient subset of the parts decodes
+		Some(fountain) => vec![fountain.decode(
+			parts
+				.values()
+				.map(Vec::as_slice)
+				.collect::<Vec<_>>()
+				.as_slice(),
+		)?],
+		None => {
+			// The part count is untrusted, only the first missing parts are listed
+			let missing_num = header.parts as usize - parts.len();
+			let mut missing = (0..header.parts)
+				.filter(|part| !parts.contains_key(part))
+				.take(MAX_MISSING)
+				.map(|part| match part_base(first_input.as_str()) {
+					Some(base) => format!("`{}`", part_path(base.as_str(), part)),
+					None => (part + 1).to_string(),
+				})
+				.collect::<Vec<_>>();
+			if missing_num > missing.len() {
+				missing.push(format!("and {} more", missing_num - missing.len()));
+			}
+			if !missing.is_empty() {
+				Err(format!(
+					"Missing part(s) {} out of {}",
+					missing.join(", "),
+					header.parts
+				))?;
+			}
+			parts.into_values().collect::<Vec<_>>()
+		}
+	};
+	if let Some(crypt) = &header.crypt {
+		let passphrase = passphrase(&matches, false)?;
+		parts = vec![crypt.decrypt(
+			passphrase.as_str(),
+			parts.concat().as_slice(),
+			header.associated_data().as_slice(),
+		)?];
+	}
+	if let Some(recipients) = &header.recipients {
+		let identity = matches
+			.opt_str("identity")
+			.ok_or("The payload is encrypted for recipients, pass their key with `--identity`")?;
+		let identity = read_key(identity.as_str(), SECRET_KEY_PREFIX)?;
+		parts = vec![recipients.decrypt(
+			&identity,
+			parts.concat().as_slice(),
+			header.associated_data().as_slice(),
+		)?];
+	}
+	if let Some(compression) = &header.compression {
+		parts = vec![compression.decompress(parts.concat().as_slice())?];
+	}
+
+	// Check digest
+	if let Some(digest) = &header.digest {
+		digest.verify(parts.iter().map(|part| part.as_slice()))?;
+	}
+
+	if let Some(archive) = &header.archive {
+		return extract(archive, parts.concat().as_slice(), output, &matches);
+	}
+
+	let output = match output {
+		Some(output) => output,
+		None => stored_output(&header)?,
+	};
+	let output_file = File::create(&output)
+		.map_err(|err| format!("Failed to create output file `{output}`: {err}"))?;
+	let mut w = BufWriter::new(output_file);
+	for part in parts {
+		w.write_all(part.as_slice())
+			.map_err(|err| format!("Failed to write to output file `{output}`: {err}"))?;
+	}
+	let output_file = w
+		.into_inner()
 		.map_err(|err| format!("Failed to write to output file `{output}`: {err}"))?;
+	restore_attributes(&header, output.as_str(), &output_file)?;
+	println!("File written to `{output}`");
+
+	Ok(())
+}
+
+/// Writes the files of an archive, or those selected with `--extract`, in the output directory
+fn extract(
+	archive: &Archive,
+	data: &[u8],
+	output: Option<String>,
+	matches: &Matches,
+) -> Result<(), String> {
+	let selected = matches.opt_strs("extract");
+	let is_selected = |selection: &str, path: &str| {
+		let selection = selection.trim_end_matches('/');
+		path == selection || path.starts_with(format!("{selection}/").as_str())
+	};
+	if let Some(selection) = selected.iter().find(|selection| {
+		!archive
+			.entries
+			.iter()
+			.any(|entry| is_selected(selection, entry.path.as_str()))
+	}) {
+		return Err(format!("`{selection}` is not in the archive"));
+	}
+
+	let output = output.unwrap_or(".".into());
+	let files = archive
+		.files(data)?
+		.into_iter()
+		.filter(|(entry, _)| {
+			selected.is_empty()
+				|| selected
+					.iter()
+					.any(|selection| is_selected(selection, entry.path.as_str()))
+		})
+		.map(|(entry, file)| (Path::new(&output).join(&entry.path), entry, file))
+		.collect::<Vec<_>>();
+
+	// Check everything before writing any file
+	for (path, entry, file) in &files {
+		entry.verify(file)?;
+		if path.exists() {
+			return Err(format!(
+				"`{}` already exists, use another output directory",
+				path.display()
+			));
+		}
+	}
+	for (path, entry, file) in files {
+		if let Some(parent) = path.parent() {
+			std::fs::create_dir_all(parent).map_err(|err| {
+				format!("Failed to create directory `{}`: {err}", parent.display())
+			})?;
+		}
+		std::fs::write(&path, file)
+			.map_err(|err| format!("Failed to write output file `{}`: {err}", path.display()))?;
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			let created = std::fs::metadata(&path)
+				.map_err(|err| {
+					format!("Failed to read permissions of `{}`: {err}", path.display())
+				})?
+				.permissions()
+				.mode();
+			if let Some(mode) = entry.restored_mode(created) {
+				std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).map_err(
+					|err| format!("Failed to set permissions of `{}`: {err}", path.display()),
+				)?;
+			}
+		}
+		println!("File written to `{}`", path.display());
+	}
+
+	Ok(())
+}
+
+fn list(input: String, _matches: Matches) -> Result<(), String> {
+	let header = read_any_header(input.as_str())?;
+
+	let Some(archive) = &header.archive else {
+		match &header.file_name {
+			Some(name) => println!("`{input}` holds a single file: {name}"),
+			None => println!("`{input}` holds a single file"),
+		}
+		return Ok(());
+	};
+	for entry in &archive.entries {
+		let checksum = entry
+			.checksum
+			.iter()
+			.map(|byte| format!("{byte:02x}"))
+			.collect::<String>();
+		println!(
+			"{:04o} {:>12} {checksum} {}",
+			entry.mode, entry.size, entry.path
+		);
+	}
+	println!("{} file(s), {}bytes", archive.entries.len(), archive.size());
+
+	Ok(())
+}
+
+fn upgrade(input: String, output: String, matches: Matches) -> Result<(), String> {
+	let (header, payload, layout) = read_payload(input.as_str())?;
+	print_header(&header, Some(payload.as_slice()));
+	header.verify(payload.as_slice(), None)?;
+
+	if header.version == Version::CURRENT {
+		eprintln!("`{input}` is already {:#?}", Version::CURRENT);
+	}
+	let signed = header.signature.is_some();
+	let mut header = header.upgrade(Format::Data);
+	match matches.opt_str("sign") {
+		Some(path) => header.sign(
+			&read_key(path.as_str(), sign::SECRET_KEY_PREFIX)?,
+			payload.as_slice(),
+		)?,
+		None if signed => {
+			eprintln!("Warning: the signature was dropped, use `--sign` to sign the image again")
+		}
+		None => {}
+	}
+	match layout {
+		Some((layout, frames, (width, height))) => {
+			// Keep the size of the image, only growing its height when the new header does not fit
+			let mut header_data = vec![];
+			header.encode(&mut header_data);
+			let size = frame_size(header_data.len(), payload.len(), frames);
+			let shape = match minimum_size(layout.0, layout.1, width, height) >= size {
+				true => Shape::Canvas(width, height),
+				false => Shape::Width(width),
+			};
+			let image = encode_image(
+				layout,
+				shape,
+				frames,
+				padding_from_matches(&matches)?,
+				&header,
+				payload.as_slice(),
+			)?;
+			std::fs::write(&output, image)
+				.map_err(|err| format!("Failed to write output file `{output}`: {err}"))?;
+			println!("File written to `{output}`");
+			Ok(())
+		}
+		None if matches.opt_present("padding") => {
+			Err("`--padding` only applies to regular images".into())
+		}
+		None => write_carrier(output.as_str(), input.as_str(), &header, payload.as_slice()),
+	}
+}
+
+fn visualize(input: String, output: String, matches: Matches) -> Result<(), String> {
+	let coloring = matches
+		.opt_str("color")
+		.map(|coloring| coloring.parse::<Coloring>())
+		.transpose()?
+		.unwrap_or(Coloring::Class);
+	let data = std::fs::read(&input)
+		.map_err(|err| format!("Failed to read input file `{input}`: {err}"))?;
+	let vis = Visualization::new(data.as_slice(), coloring, matches.opt_present("hilbert"))?;
+
+	let output_file = File::create(&output)
+		.map_err(|err| format!("Failed to open output file `{output}`: {err}"))?;
+	let mut encoder = png::Encoder::new(BufWriter::new(output_file), vis.width, vis.height);
+	encoder.set_color(ColorType::Rgb);
+	encoder.set_depth(BitDepth::Eight);
+	encoder
+		.write_header()
+		.and_then(|mut writer| writer.write_image_data(vis.pixels.as_slice()))
+		.map_err(|err| format!("Failed to write image `{output}`: {err}"))?;
 	println!("File written to `{output}`");
 
 	Ok(())
 }
 
+fn keygen(output: String, matches: Matches) -> Result<(), String> {
+	let ((secret, public), secret_prefix, public_prefix) = if matches.opt_present("signing") {
+		(
+			sign::generate_keys(),
+			sign::SECRET_KEY_PREFIX,
+			sign::PUBLIC_KEY_PREFIX,
+		)
+	} else {
+		(generate_keys(), SECRET_KEY_PREFIX, PUBLIC_KEY_PREFIX)
+	};
+
+	// Only the owner may read the secret key
+	let mut options = std::fs::OpenOptions::new();
+	options.write(true).create_new(true);
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::OpenOptionsExt;
+		options.mode(0o600);
+	}
+	let mut file = options
+		.open(&output)
+		.map_err(|err| format!("Failed to create key file `{output}`: {err}"))?;
+	writeln!(file, "{}", encode_key(secret_prefix, &secret))
+		.map_err(|err| format!("Failed to write key file `{output}`: {err}"))?;
+
+	let public_output = format!("{output}.pub");
+	let public = encode_key(public_prefix, &public);
+	std::fs::write(&public_output, format!("{public}\n"))
+		.map_err(|err| format!("Failed to write key file `{public_output}`: {err}"))?;
+	println!("Keys written to `{output}` and `{public_output}`\nPublic key: {public}");
+
+	Ok(())
+}
+
 fn main() -> ExitCode {
 	let args: Vec<String> = env::args().collect();
 	let program = args[0].clone();
 
 	let mut opts = Options::new();
-	opts.optopt("e", "encode", "Embed file", "FILE");
+	opts.optopt(
+		"e",
+		"encode",
+		"Embed file, further files and directories are archived with it",
+		"FILE",
+	);
 	opts.optopt("d", "decode", "Decode mode", "FILE");
 	opts.optopt("z", "info", "Read header", "FILE");
+	opts.optopt(
+		"",
+		"upgrade",
+		"Rewrite an image with the current header version",
+		"FILE",
+	);
+	opts.optopt("", "list", "Lists the files of an archive image", "FILE");
+	opts.optopt(
+		"",
+		"visualize",
+		"Renders a file's structure as an image, it can not be decoded back",
+		"FILE",
+	);
+	opts.optopt(
+		"",
+		"color",
+		"With `--visualize`, colors bytes by class or by local entropy: class or entropy",
+		"COLORING",
+	);
+	opts.optflag(
+		"",
+		"hilbert",
+		"With `--visualize`, lays bytes along a Hilbert curve so nearby bytes stay close",
+	);
 	opts.optopt("l", "layout", "Png image layout", "TXT");
-	opts.optopt("o", "output", "Output file", "PATH");
+	opts.optopt(
+		"o",
+		"output",
+		"Output file, or directory of an archive",
+		"PATH",
+	);
+	opts.optmulti(
+		"",
+		"extract",
+		"Only extracts this file or directory of an archive, may be repeated",
+		"PATH",
+	);
 	opts.optopt("c", "comment", "Header comment", "TXT");
+	opts.optmulti(
+		"m",
+		"meta",
+		"Header metadata, may be repeated: key=text, key:int=42 or key:hex=c0ffee",
+		"KEY=VALUE",
+	);
+	opts.optopt(
+		"",
+		"digest",
+		"Stores a digest of the file to verify on decode: sha256 or blake3",
+		"ALG",
+	);
+	opts.optopt(
+		"",
+		"compress",
+		"Compresses the file: deflate, zstd or xz",
+		"CODEC",
+	);
+	opts.optflag("", "encrypt", "Encrypts the file with a passphrase");
+	opts.optopt(
+		"",
+		"passphrase-file",
+		"Reads the passphrase from the first line of a file instead of prompting for it",
+		"FILE",
+	);
+	opts.optmulti(
+		"r",
+		"recipient",
+		"Encrypts the file for a public key, may be repeated",
+		"FILE",
+	);
+	opts.optopt(
+		"",
+		"identity",
+		"Secret key decrypting a file encrypted for recipients",
+		"FILE",
+	);
+	opts.optopt(
+		"",
+		"keygen",
+		"Generates a key pair for recipient encryption",
+		"FILE",
+	);
+	opts.optflag(
+		"",
+		"signing",
+		"With `--keygen`, generates an Ed25519 signing key pair instead",
+	);
+	opts.optopt(
+		"",
+		"max-bytes",
+		"Splits the payload in images of at most this many bytes",
+		"BYTES",
+	);
+	opts.optopt(
+		"",
+		"max-dimension",
+		"Splits the payload in images at most this wide and high",
+		"PIXELS",
+	);
+	opts.optopt(
+		"",
+		"fountain",
+		"Spreads the payload over IMAGES images with RaptorQ, any NEEDED of which decode it",
+		"NEEDED/IMAGES",
+	);
+	opts.optopt(
+		"",
+		"width",
+		"Writes images of this width instead of near-square images",
+		"PIXELS",
+	);
+	opts.optopt(
+		"",
+		"aspect",
+		"Writes images with this ratio of the width to the height",
+		"W:H",
+	);
+	opts.optopt(
+		"",
+		"canvas",
+		"Writes images of exactly this size, padding the unused pixels",
+		"WxH",
+	);
+	opts.optopt(
+		"",
+		"padding",
+		"Fills the pixels after the data with random bytes seeded from the data (hash, the default), zeros (zero) or random bytes differing on every run (random)",
+		"hash|zero|random",
+	);
+	opts.optopt(
+		"",
+		"frames",
+		"Spreads each image over this many frames of an animated png, the header being on the first",
+		"COUNT",
+	);
+	opts.optopt(
+		"",
+		"carrier",
+		"Stores the file in a private chunk of a copy of this png image, leaving its pixels untouched",
+		"FILE",
+	);
+	opts.optflag(
+		"",
+		"zip",
+		"Appends a zip of the files to the image, which then also opens as a zip archive",
+	);
+	opts.optflag(
+		"",
+		"paper",
+		"Encodes or decodes a printable code that survives being printed and scanned",
+	);
+	opts.optopt(
+		"",
+		"module",
+		"With `--paper`, the size of each bit in pixels, 8 by default",
+		"PIXELS",
+	);
+	opts.optopt(
+		"",
+		"fec",
+		"Adds Reed-Solomon error correction with a redundancy in percent, from 1 to 200",
+		"PERCENT",
+	);
+	opts.optopt(
+		"",
+		"sign",
+		"Signs the image with an Ed25519 secret key",
+		"FILE",
+	);
+	opts.optopt(
+		"",
+		"verify",
+		"Requires the image to be signed by an Ed25519 public key",
+		"FILE",
+	);
+	opts.optflag(
+		"",
+		"no-attributes",
+		"Do not store the file name, permissions and modification time",
+	);
 	opts.optflag("h", "help", "Print this help menu");
 	opts.optflag("v", "version", "Print program version and licenses");
 
@@ -280,20 +1882,21 @@ fn main() -> ExitCode {
 	if matches.opt_present("e") as usize
 		+ matches.opt_present("d") as usize
 		+ matches.opt_present("z") as usize
+		+ matches.opt_present("upgrade") as usize
+		+ matches.opt_present("keygen") as usize
+		+ matches.opt_present("list") as usize
+		+ matches.opt_present("visualize") as usize
 		> 1
 	{
-		eprintln!("Specify either `-e(--encode)`, `-z(--info)` or `-d(--decode)`");
+		eprintln!(
+			"Specify either `-e(--encode)`, `-z(--info)`, `-d(--decode)`, `--list`, `--visualize`, `--upgrade` or `--keygen`"
+		);
 		return ExitCode::FAILURE;
 	}
 
 	if let Some(input_file) = matches.opt_str("e") {
-		let layout = match matches.opt_str("l") {
-			None => {
-				eprintln!("Missing required png layout (-l|--layout) option");
-				return ExitCode::FAILURE;
-			}
-			Some(layout) => layout,
-		};
+		// Printed codes and carrier images have no layout
+		let layout = matches.opt_str("l");
 
 		let output_file = match matches.opt_str("o") {
 			None => {
@@ -313,6 +1916,19 @@ fn main() -> ExitCode {
 			return ExitCode::FAILURE;
 		}
 	} else if let Some(input_file) = matches.opt_str("d") {
+		// Defaults to the file name stored in the header
+		let output_file = matches.opt_str("o");
+
+		if let Err(e) = decode(input_file, output_file, matches) {
+			eprintln!("{e}");
+			return ExitCode::FAILURE;
+		}
+	} else if let Some(input_file) = matches.opt_str("list") {
+		if let Err(e) = list(input_file, matches) {
+			eprintln!("{e}");
+			return ExitCode::FAILURE;
+		}
+	} else if let Some(input_file) = matches.opt_str("visualize") {
 		let output_file = match matches.opt_str("o") {
 			None => {
 				eprintln!("Missing required output (-o|--output) option");
@@ -321,7 +1937,25 @@ fn main() -> ExitCode {
 			Some(output_file) => output_file,
 		};
 
-		if let Err(e) = decode(input_file, output_file, matches) {
+		if let Err(e) = visualize(input_file, output_file, matches) {
+			eprintln!("{e}");
+			return ExitCode::FAILURE;
+		}
+	} else if let Some(input_file) = matches.opt_str("upgrade") {
+		let output_file = match matches.opt_str("o") {
+			None => {
+				eprintln!("Missing required output (-o|--output) option");
+				return ExitCode::FAILURE;
+			}
+			Some(output_file) => output_file,
+		};
+
+		if let Err(e) = upgrade(input_file, output_file, matches) {
+			eprintln!("{e}");
+			return ExitCode::FAILURE;
+		}
+	} else if let Some(output_file) = matches.opt_str("keygen") {
+		if let Err(e) = keygen(output_file, matches) {
 			eprintln!("{e}");
 			return ExitCode::FAILURE;
 		}
@@ -331,3 +1965,119 @@ fn main() -> ExitCode {
 	}
 	ExitCode::SUCCESS
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Lays out the header copies and the payload as [`encode_image`] does, in `len` bytes
+	fn image_data(payload: &[u8], len: usize) -> (Vec<u8>, usize) {
+		let header = Header::new(Format::Data, Version::CURRENT, payload, vec![]).unwrap();
+		let mut header_data = vec![];
+		header.encode(&mut header_data);
+		let mut data = vec![0; len];
+		let [start, middle, end] = header_copies(len, header_data.len());
+		data[start.clone()].copy_from_slice(&header_data);
+		data[middle.clone()].copy_from_slice(&header_data);
+		header_data.reverse();
+		data[end].copy_from_slice(&header_data);
+		data[start.end..start.end + payload.len()].copy_from_slice(payload);
+		(data, header_data.len())
+	}
+
+	fn read_payload(data: &[u8]) -> Result<Vec<u8>, String> {
+		let (_, ranges) = read_header("test", data)?;
+		Ok(ranges
+			.into_iter()
+			.flat_map(|range| data[range].to_vec())
+			.collect())
+	}
+
+	#[test]
+	fn damaged_first_copy() {
+		let (mut data, header_len) = image_data(b"payload", 1000);
+		for byte in &mut data[..header_len] {
+			*byte ^= 0x5A;
+		}
+		assert_eq!(read_payload(&data).unwrap(), b"payload");
+
+		// The end copy is used when the middle one is damaged too
+		for byte in &mut data[500..500 + header_len] {
+			*byte ^= 0x5A;
+		}
+		assert_eq!(read_payload(&data).unwrap(), b"payload");
+	}
+
+	#[test]
+	fn damaged_copies_majority() {
+		let (mut data, header_len) = image_data(b"payload", 1000);
+		// Each copy is damaged in a different place
+		data[1] ^= 0xFF;
+		data[500 + 2] ^= 0xFF;
+		data[999 - 3] ^= 0xFF;
+		assert_eq!(read_payload(&data).unwrap(), b"payload");
+
+		// Without a majority, the image can not be read
+		for copy in [
+			0..header_len,
+			500..500 + header_len,
+			1000 - header_len..1000,
+		] {
+			data[copy.start + 4] ^= 0x0F;
+		}
+		assert!(read_payload(&data).is_err());
+	}
+
+	#[test]
+	fn part_paths() {
+		assert_eq!(part_path("out.png", 0), "out.001.png");
+		assert_eq!(part_path("dir/out.png", 41), "dir/out.042.png");
+		assert_eq!(part_path("out", 1), "out.002");
+		assert_eq!(part_path("out.png", 1233), "out.1234.png");
+
+		assert_eq!(part_base("dir/out.042.png").as_deref(), Some("dir/out.png"));
+		assert_eq!(part_base("out.1234.png").as_deref(), Some("out.png"));
+		assert_eq!(part_base("out.png"), None);
+		assert_eq!(part_base("out.v2.png"), None);
+		assert_eq!(part_base("out.01.png"), None);
+		for part in [0, 9, 999] {
+			assert_eq!(
+				part_base(&part_path("a.b.png", part)).as_deref(),
+				Some("a.b.png")
+			);
+		}
+	}
+
+	#[test]
+	fn part_capacities() {
+		let layouts = [
+			(ColorType::Rgb, BitDepth::Eight),
+			(ColorType::Grayscale, BitDepth::One),
+			(ColorType::Indexed, BitDepth::Eight),
+			(ColorType::Rgba, BitDepth::Sixteen),
+		];
+		let shapes = [Shape::Square, Shape::Width(7), Shape::Aspect(16, 9)];
+		for (layout, shape, frames) in layouts
+			.into_iter()
+			.flat_map(|layout| shapes.map(|shape| (layout, shape)))
+			.flat_map(|(layout, shape)| [1, 3].map(|frames| (layout, shape, frames)))
+		{
+			let len = part_capacity(layout, shape, frames, MAX_DIMENSION, Some(20000));
+			let header = Header::new(Format::Data, Version::CURRENT, &[], vec![]).unwrap();
+			let mut header_data = vec![];
+			header.encode(&mut header_data);
+
+			// Incompressible data filling the whole part still fits the limit
+			let mut payload = vec![0; len - header_data.len() * 3];
+			ChaCha8Rng::seed_from_u64(1).fill(payload.as_mut_slice());
+			let header = Header::new(Format::Data, Version::CURRENT, &payload, vec![]).unwrap();
+			let image =
+				encode_image(layout, shape, frames, Padding::Random, &header, &payload).unwrap();
+			assert!(
+				image.len() <= 20000,
+				"{layout:?} {shape:?} x{frames}: {}bytes",
+				image.len()
+			);
+		}
+	}
+}
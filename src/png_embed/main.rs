@@ -1,7 +1,3 @@
-mod block;
-mod embed;
-mod ent;
-mod header;
 mod image;
 
 use std::env;
@@ -12,18 +8,36 @@ use std::process::ExitCode;
 use std::str::FromStr;
 
 use bitvec::prelude::*;
-use block::best_blocksize;
-use block::BlockPlacement;
-use block::BlockPlacementIterator;
 use crc::Crc;
-use embed::EmbedAlgorithm;
-use ent::EntropyGenerator;
 use getopts::Matches;
 use getopts::Options;
-use header::Decode;
-use header::Encode;
-use header::Header;
 use image::ImageInfo;
+use png_data::block;
+use png_data::block::best_blocksize;
+use png_data::block::block_order;
+use png_data::block::block_spans;
+use png_data::block::embed_spans;
+use png_data::block::header_copies;
+use png_data::block::BlockPlacement;
+use png_data::block::BlockPlacementIterator;
+use png_data::compress::Codec;
+use png_data::compress::Compression;
+use png_data::crypt::read_key;
+use png_data::embed::default_seed;
+use png_data::embed::derive_seed;
+use png_data::embed::EmbedAlgorithm;
+use png_data::ent::EntropyGenerator;
+use png_data::header::Decode;
+use png_data::header::Digest;
+use png_data::header::DigestAlgorithm;
+use png_data::header::Encode;
+use png_data::header::Format;
+use png_data::header::Header;
+use png_data::header::Meta;
+use png_data::header::Version;
+use png_data::header::COMMENT_KEY;
+use png_data::sign;
+use png_data::sign::fingerprint;
 use rand::SeedableRng;
 use rand_chacha::ChaCha8Rng;
 
@@ -32,7 +46,8 @@ fn print_usage(program: &str, opts: Options) {
 		"Usage: {0} -l ALGORITHM -(e|z|d) [EMBED] FILE -o OUTPUT [opts]
 		Encode: {0} -l lo3 -e embed.jpg input.png -o out.png -c \"Embedded JPEG file\"
 		Info:   {0} -l lo3 out.png # Embedded JPEG file
-		Decode: {0} -l lo3 -d out.png -o decoded.jpg",
+		Decode: {0} -l lo3 -d out.png -o decoded.jpg
+		Upgrade: {0} -l lo3 --upgrade old.png -o new.png",
 		program
 	);
 	print!("{}", opts.usage(&brief));
@@ -55,8 +70,6 @@ impl ImageInfo for png::OutputInfo {
 
 	fn height(&self) -> u32 { self.height }
 
-	fn size(&self) -> usize { self.buffer_size() }
-
 	fn encode(&self, w: &mut BufWriter<Box<dyn Write>>, data: Vec<u8>) {
 		let mut encoder = png::Encoder::new(w, self.width(), self.height());
 		encoder.set_color(self.color_type);
@@ -87,68 +100,88 @@ fn decode_image(image: &str) -> Result<(Vec<u8>, Box<dyn ImageInfo>), String> {
 	}
 }
 
-// Derives the seed from a given string.
-// Currently using Argon with salt: `png_data embed`
-fn derive_seed(seed: &str) -> Result<[u8; 32], String> {
-	let mut result = [0u8; 32];
-	argon2::Argon2::default()
-		.hash_password_into(seed.as_bytes(), b"png_data embed", &mut result)
-		.map_err(|err| format!("Failed to derive seed `{seed}`: {err}"))?;
-	Ok(result)
+/// Gets the header metadata from the `-c` and `--meta` options
+fn metadata(matches: &Matches) -> Result<Vec<Meta>, String> {
+	let mut metadata = matches
+		.opt_strs("m")
+		.iter()
+		.map(|meta| meta.parse::<Meta>())
+		.collect::<Result<Vec<_>, _>>()?;
+	if let Some(comment) = matches.opt_str("c") {
+		metadata.insert(0, Meta::text(COMMENT_KEY, comment));
+	}
+	Ok(metadata)
 }
 
-fn encode(
-	input: String,
-	embed: String,
-	output: String,
-	algorithm: String,
-	matches: Matches,
-) -> Result<(), String> {
-	let algorithm = EmbedAlgorithm::from_str(algorithm.as_str())?;
-
-	let (mut data, info) = decode_image(input.as_str())?;
-	let block_size = best_blocksize(info.size());
-	let seed = derive_seed(
+/// Gets the placement seed, defaults to "{width}x{height}"
+fn seed(matches: &Matches, info: &dyn ImageInfo) -> Result<[u8; 32], String> {
+	derive_seed(
 		matches
 			.opt_str("s")
-			.unwrap_or(format!("{}x{}", info.width(), info.height()))
+			.unwrap_or(default_seed(info.width(), info.height()))
 			.as_str(),
-	)?;
-	let comment = matches.opt_str("c");
+	)
+}
 
-	// Data
-	let embed_file_data = std::fs::read(&embed)
-		.map_err(|err| format!("Failed to read embed file `{embed}`: {err}"))?;
+/// Displays a header, verifying its signature when the payload is given
+fn print_header(header: &Header, payload: Option<&[u8]>) {
+	eprintln!("Version: {:#?}", header.version);
+	if !header.metadata.is_empty() {
+		eprintln!("Metadata:");
+		for meta in &header.metadata {
+			eprintln!("  {meta}");
+		}
+	}
+	eprintln!("Data: {}bytes CRC[{:X}]", header.data_len, header.data_crc);
+	if let Some(compression) = &header.compression {
+		eprintln!(
+			"Compression: {}, {}bytes uncompressed",
+			compression.codec, compression.original_len
+		);
+	}
+	if let Some(digest) = &header.digest {
+		eprintln!("Digest: {digest}");
+	}
+	if let Some(signature) = &header.signature {
+		let signer = fingerprint(&signature.public_key);
+		match payload.map(|payload| header.verify(payload, None)) {
+			Some(Ok(())) => eprintln!("Signed by: {signer}"),
+			Some(Err(_)) => eprintln!("Invalid signature by: {signer}"),
+			None => eprintln!("Unverified signature by: {signer}"),
+		}
+	}
+}
 
-	// Header
-	let header = Header::new(
-		header::Version::VERSION_1,
-		embed_file_data.as_slice(),
-		comment,
-	)?;
+/// Embeds a header and its payload into the image data
+fn write_embed(
+	algorithm: &EmbedAlgorithm,
+	data: &mut [u8],
+	seed: [u8; 32],
+	header: &Header,
+	payload: &[u8],
+	matches: &Matches,
+) -> Result<(), String> {
+	let block_size = best_blocksize(data.len());
 
 	// Result
-	let mut embed_data = vec![];
-	header.encode(&mut embed_data);
-	embed_data.extend(embed_file_data);
+	let mut header_data = vec![];
+	header.encode(&mut header_data);
+	let mut embed_data = header_data.clone();
+	embed_data.extend_from_slice(payload);
 
 	eprintln!("=== HEADER ===");
-	eprintln!("Version: {:#?}", header.version);
-	eprintln!(
-		"Comment: {}",
-		header.comment.as_ref().map_or("", |c| c.as_str())
-	);
-	eprintln!("Data: {}bytes CRC[{:X}]", header.data_len, header.data_crc);
+	print_header(header, Some(payload));
 	eprintln!("Block: {block_size}bytes");
 
 	let mut rand = ChaCha8Rng::from_seed(seed);
-	let mut placement = BlockPlacement::new(
-		&algorithm,
-		data.as_mut_slice(),
-		block_size,
-		embed_data.len(),
-		&mut rand,
+	let order = block_order(data.len(), block_size, &mut rand);
+	let copies = header_copies(
+		order.len() * block_size,
+		algorithm.embedded_size(header_data.len()),
 	)?;
+	let blocks = embed_spans(&order, block_size, &copies);
+
+	let mut placement = BlockPlacement::new(algorithm, data, embed_data.len(), blocks)?;
 
 	eprintln!("Required blocks: {}", placement.blocks.len());
 	eprintln!("==============");
@@ -163,37 +196,154 @@ fn encode(
 		))
 	}
 
-	let outfile = File::create(&output).unwrap();
+	// Header copies
+	for copy in copies {
+		let blocks = block_spans(&order, block_size, copy);
+		BlockPlacement::new(algorithm, data, header_data.len(), blocks)?
+			.write_embed(header_data.as_slice().view_bits::<Lsb0>());
+	}
+
+	Ok(())
+}
+
+/// Decodes the embed header, telling apart images written by other tools
+fn read_header<'a>(
+	algorithm: &'a EmbedAlgorithm,
+	data: &'a [u8],
+	seed: [u8; 32],
+) -> Result<(Header, BlockPlacementIterator<'a>), String> {
+	let (header, it) = block::read_header(algorithm, data, seed).map_err(|err| {
+		// png_data images hold their header as is
+		match Header::decode(&mut data.iter().copied())
+			.map(|header| header.check_format(Format::Embed))
+		{
+			Ok(Err(err)) => err,
+			_ => format!("{err}, wrong algorithm or seed, or no embed in this image"),
+		}
+	})?;
+	header.check_format(Format::Embed)?;
+	Ok((header, it))
+}
+
+/// Reads the header and the checked payload embedded in the image data
+fn read_embed(
+	algorithm: &EmbedAlgorithm,
+	data: &[u8],
+	seed: [u8; 32],
+) -> Result<(Header, Vec<u8>), String> {
+	let (header, mut it) = read_header(algorithm, data, seed)?;
+
+	// An embed can never be larger than the image holding it
+	if header.data_len > data.len() as u64 {
+		Err(format!(
+			"Header claims {}bytes of data but the image only holds {}bytes",
+			header.data_len,
+			data.len()
+		))?;
+	}
+
+	let mut data = Vec::with_capacity(header.data_len as usize);
+	while data.len() < header.data_len as usize {
+		data.push(
+			it.next()
+				.ok_or(format!("Failed to read data byte at {}", data.len()))?,
+		);
+	}
+
+	// Check CRC
+	let data_crc = Crc::<u32>::new(&crc::CRC_32_CKSUM).checksum(data.as_slice());
+	if data_crc != header.data_crc {
+		Err(format!(
+			"Data CRC do not match: HEADER={:X} GOT={data_crc:X}",
+			header.data_crc
+		))?;
+	}
+
+	Ok((header, data))
+}
+
+/// Writes the image data to a png file
+fn write_image(output: &str, info: &dyn ImageInfo, data: Vec<u8>) -> Result<(), String> {
+	let outfile = File::create(output)
+		.map_err(|e| format!("Failed to create output file `{output}`: {e}"))?;
 	let w = &mut BufWriter::new(Box::new(outfile) as Box<dyn Write>);
 	info.encode(w, data);
 
 	Ok(())
 }
 
-fn decode_header(input: String, algorithm: String, matches: Matches) -> Result<(), String> {
+fn encode(
+	input: String,
+	embed: String,
+	output: String,
+	algorithm: String,
+	matches: Matches,
+) -> Result<(), String> {
 	let algorithm = EmbedAlgorithm::from_str(algorithm.as_str())?;
 
-	let (data, info) = decode_image(input.as_str())?;
-	let block_size = best_blocksize(info.size());
-	let seed = derive_seed(
-		matches
-			.opt_str("s")
-			.unwrap_or(format!("{}x{}", info.width(), info.height()))
-			.as_str(),
+	let (mut data, info) = decode_image(input.as_str())?;
+	let seed = seed(&matches, info.as_ref())?;
+	let metadata = metadata(&matches)?;
+
+	// Data
+	let embed_file_data = std::fs::read(&embed)
+		.map_err(|err| format!("Failed to read embed file `{embed}`: {err}"))?;
+
+	let digest = matches
+		.opt_str("digest")
+		.map(|algorithm| algorithm.parse::<DigestAlgorithm>())
+		.transpose()?
+		.map(|algorithm| Digest::new(algorithm, [embed_file_data.as_slice()]));
+	let (embed_file_data, compression) = match matches.opt_str("compress") {
+		Some(codec) => {
+			let (data, compression) =
+				Compression::compress(codec.parse::<Codec>()?, &embed_file_data)?;
+			(data, Some(compression))
+		}
+		None => (embed_file_data, None),
+	};
+
+	// Header
+	let mut header = Header::new(
+		Format::Embed,
+		Version::CURRENT,
+		embed_file_data.as_slice(),
+		metadata,
 	)?;
+	header.digest = digest;
+	header.compression = compression;
+	if let Some(path) = matches.opt_str("sign") {
+		header.sign(
+			&read_key(path.as_str(), sign::SECRET_KEY_PREFIX)?,
+			embed_file_data.as_slice(),
+		)?;
+	}
 
-	let mut rand = ChaCha8Rng::from_seed(seed);
-	let mut it = BlockPlacementIterator::new(&algorithm, data.as_slice(), block_size, &mut rand);
+	write_embed(
+		&algorithm,
+		data.as_mut_slice(),
+		seed,
+		&header,
+		embed_file_data.as_slice(),
+		&matches,
+	)?;
+	write_image(output.as_str(), info.as_ref(), data)
+}
 
-	let header = Header::decode(&mut it)?;
+fn decode_header(input: String, algorithm: String, matches: Matches) -> Result<(), String> {
+	let algorithm = EmbedAlgorithm::from_str(algorithm.as_str())?;
+
+	let (data, info) = decode_image(input.as_str())?;
+	let seed = seed(&matches, info.as_ref())?;
+
+	// The payload is needed to verify the signature, damaged payloads only show the header
+	let (header, payload) = match read_embed(&algorithm, data.as_slice(), seed) {
+		Ok((header, payload)) => (header, Some(payload)),
+		Err(_) => (read_header(&algorithm, data.as_slice(), seed)?.0, None),
+	};
 
 	eprintln!("=== HEADER ===");
-	eprintln!("Version: {:#?}", header.version);
-	eprintln!(
-		"Comment: \"{}\"",
-		header.comment.as_ref().map_or("", |c| c.as_str())
-	);
-	eprintln!("Data: {}bytes CRC[{:X}]", header.data_len, header.data_crc);
+	print_header(&header, payload.as_deref());
 	eprintln!("==============");
 
 	Ok(())
@@ -208,34 +358,27 @@ fn decode(
 	let algorithm = EmbedAlgorithm::from_str(algorithm.as_str())?;
 
 	let (data, info) = decode_image(input.as_str())?;
-	let block_size = best_blocksize(info.size());
-	let seed = derive_seed(
-		matches
-			.opt_str("s")
-			.unwrap_or(format!("{}x{}", info.width(), info.height()))
-			.as_str(),
-	)?;
-
-	let mut rand = ChaCha8Rng::from_seed(seed);
-	let mut it = BlockPlacementIterator::new(&algorithm, data.as_slice(), block_size, &mut rand);
-
-	let header = Header::decode(&mut it)?;
-
-	let mut data = Vec::with_capacity(header.data_len as usize);
-	while data.len() < header.data_len as usize {
-		data.push(
-			it.next()
-				.ok_or(format!("Failed to read data byte at {}", data.len()))?,
-		);
+	let seed = seed(&matches, info.as_ref())?;
+
+	let (header, mut data) = read_embed(&algorithm, data.as_slice(), seed)?;
+	let verify_key = matches
+		.opt_str("verify")
+		.map(|path| read_key(path.as_str(), sign::PUBLIC_KEY_PREFIX))
+		.transpose()?;
+	header.verify(data.as_slice(), verify_key.as_ref())?;
+	if header.crypt.is_some() || header.recipients.is_some() {
+		return Err("Encrypted payloads are only supported by png_data".into());
+	}
+	if header.fec.is_some() {
+		return Err("Error correction is only supported by png_data".into());
+	}
+	if let Some(compression) = &header.compression {
+		data = compression.decompress(data.as_slice())?;
 	}
 
-	// Check CRC
-	let data_crc = Crc::<u32>::new(&crc::CRC_32_CKSUM).checksum(data.as_slice());
-	if data_crc != header.data_crc {
-		Err(format!(
-			"Data CRC do not match: HEADER={:X} GOT={data_crc:X}",
-			header.data_crc
-		))?;
+	// Check digest
+	if let Some(digest) = &header.digest {
+		digest.verify([data.as_slice()])?;
 	}
 
 	let outfile = File::create(&output)
@@ -249,6 +392,46 @@ fn decode(
 	Ok(())
 }
 
+fn upgrade(
+	input: String,
+	output: String,
+	algorithm: String,
+	matches: Matches,
+) -> Result<(), String> {
+	let algorithm = EmbedAlgorithm::from_str(algorithm.as_str())?;
+
+	let (mut data, info) = decode_image(input.as_str())?;
+	let seed = seed(&matches, info.as_ref())?;
+
+	let (header, payload) = read_embed(&algorithm, data.as_slice(), seed)?;
+	header.verify(payload.as_slice(), None)?;
+	if header.version == Version::CURRENT {
+		eprintln!("`{input}` is already {:#?}", Version::CURRENT);
+	}
+	let signed = header.signature.is_some();
+	let mut header = header.upgrade(Format::Embed);
+	match matches.opt_str("sign") {
+		Some(path) => header.sign(
+			&read_key(path.as_str(), sign::SECRET_KEY_PREFIX)?,
+			payload.as_slice(),
+		)?,
+		None if signed => {
+			eprintln!("Warning: the signature was dropped, use `--sign` to sign the image again")
+		}
+		None => {}
+	}
+
+	write_embed(
+		&algorithm,
+		data.as_mut_slice(),
+		seed,
+		&header,
+		payload.as_slice(),
+		&matches,
+	)?;
+	write_image(output.as_str(), info.as_ref(), data)
+}
+
 fn main() -> ExitCode {
 	let args: Vec<String> = env::args().collect();
 	let program = args[0].clone();
@@ -258,6 +441,36 @@ fn main() -> ExitCode {
 	opts.optopt("o", "output", "Output file", "PATH");
 	opts.optflag("d", "decode", "Decode mode");
 	opts.optopt("c", "comment", "Header comment", "TXT");
+	opts.optmulti(
+		"m",
+		"meta",
+		"Header metadata, may be repeated: key=text, key:int=42 or key:hex=c0ffee",
+		"KEY=VALUE",
+	);
+	opts.optopt(
+		"",
+		"digest",
+		"Stores a digest of the embed to verify on decode: sha256 or blake3",
+		"ALG",
+	);
+	opts.optopt(
+		"",
+		"compress",
+		"Compresses the embed: deflate, zstd or xz",
+		"CODEC",
+	);
+	opts.optopt(
+		"",
+		"sign",
+		"Signs the embed with an Ed25519 secret key, see `png_data --keygen --signing`",
+		"FILE",
+	);
+	opts.optopt(
+		"",
+		"verify",
+		"Requires the embed to be signed by an Ed25519 public key",
+		"FILE",
+	);
 	opts.optopt(
 		"s",
 		"seed",
@@ -265,6 +478,11 @@ fn main() -> ExitCode {
 		"TXT",
 	);
 	opts.optflag("z", "info", "Read header");
+	opts.optflag(
+		"",
+		"upgrade",
+		"Rewrite the embed with the current header version",
+	);
 	opts.optopt("l", "algorithm", "Embed algorithm", "lo3");
 	opts.optflag(
 		"n",
@@ -301,9 +519,10 @@ fn main() -> ExitCode {
 	if matches.opt_present("e") as usize
 		+ matches.opt_present("d") as usize
 		+ matches.opt_present("z") as usize
+		+ matches.opt_present("upgrade") as usize
 		> 1
 	{
-		eprintln!("Specify either `-e(--embed)`, `-z(--info)` or `-d(--decode)`");
+		eprintln!("Specify either `-e(--embed)`, `-z(--info)`, `-d(--decode)` or `--upgrade`");
 		return ExitCode::FAILURE;
 	} else if !matches.opt_present("l") {
 		eprintln!("Missing algorithm name");
@@ -341,6 +560,17 @@ fn main() -> ExitCode {
 			eprintln!("{e}");
 			return ExitCode::FAILURE;
 		}
+	} else if matches.opt_present("upgrade") {
+		if !matches.opt_present("o") {
+			eprintln!("Missing -o(utput) file");
+			return ExitCode::FAILURE;
+		}
+		let output_file = matches.opt_str("o").unwrap();
+
+		if let Err(e) = upgrade(input_file, output_file, algorithm, matches) {
+			eprintln!("{e}");
+			return ExitCode::FAILURE;
+		}
 	} else {
 		print_usage(&program, opts);
 		return ExitCode::FAILURE;
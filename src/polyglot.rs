@@ -0,0 +1,86 @@
+use std::ffi::OsStr;
+use std::io::Cursor;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+
+use zip::write::SimpleFileOptions;
+use zip::CompressionMethod;
+use zip::DateTime;
+use zip::ZipWriter;
+
+use crate::header::civil_time;
+use crate::header::Header;
+
+/// Appends a zip of the original files after the end of a png image. Zip offsets are counted from
+/// the start of the file, so the image stays a valid png and also opens as a zip
+pub fn append_zip(
+	image: Vec<u8>,
+	input: &str,
+	header: &Header,
+	data: &[u8],
+) -> Result<Vec<u8>, String> {
+	let mut cursor = Cursor::new(image);
+	cursor
+		.seek(SeekFrom::End(0))
+		.map_err(|err| format!("{err}"))?;
+	let mut zip = ZipWriter::new(cursor);
+
+	let files = match &header.archive {
+		Some(archive) => archive
+			.files(data)?
+			.into_iter()
+			.map(|(entry, file)| {
+				(
+					entry.path.clone(),
+					file,
+					Some(entry.mode).filter(|mode| *mode != 0),
+					None,
+				)
+			})
+			.collect::<Vec<_>>(),
+		None => {
+			let name = match &header.file_name {
+				Some(name) => name.clone(),
+				None => Path::new(input)
+					.file_name()
+					.and_then(OsStr::to_str)
+					.unwrap_or("data")
+					.to_string(),
+			};
+			vec![(name, data, header.file_mode, header.file_mtime)]
+		}
+	};
+	for (name, file, mode, mtime) in files {
+		let mut options = SimpleFileOptions::default()
+			.compression_method(CompressionMethod::Deflated)
+			.large_file(file.len() as u64 >= u32::MAX as u64);
+		if let Some(mode) = mode {
+			options = options.unix_permissions(mode);
+		}
+		// Zip dates only cover 1980 to 2107
+		if let Some(mtime) = mtime.and_then(|mtime| {
+			let [year, month, day, hours, minutes, seconds] = civil_time(mtime);
+			DateTime::from_date_and_time(
+				u16::try_from(year).ok()?,
+				month as u8,
+				day as u8,
+				hours as u8,
+				minutes as u8,
+				seconds as u8,
+			)
+			.ok()
+		}) {
+			options = options.last_modified_time(mtime);
+		}
+		zip.start_file(name.as_str(), options)
+			.map_err(|err| format!("Failed to add `{name}` to the zip: {err}"))?;
+		zip.write_all(file)
+			.map_err(|err| format!("Failed to add `{name}` to the zip: {err}"))?;
+	}
+	Ok(zip
+		.finish()
+		.map_err(|err| format!("Failed to write the zip: {err}"))?
+		.into_inner())
+}
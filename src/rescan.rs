@@ -0,0 +1,279 @@
+use png::BitDepth;
+use png::ColorType;
+
+use crate::header::decode_copy;
+use crate::header::header_copies;
+use crate::header::Format;
+use crate::header::Version;
+use crate::shape::minimum_size;
+use crate::shape::Layout;
+
+/// Image data and layout
+pub type Image = (Vec<u8>, png::OutputInfo);
+
+/// Largest border around a scaled up image, in pixels of the scaled image
+const MAX_BORDER: usize = 256;
+/// Largest integer scale of a scaled up image
+const MAX_SCALE: usize = 8;
+
+/// Original pixel grid of an image scaled up by an integer factor and framed by a border, e.g
+/// by a screenshot
+#[derive(Clone)]
+struct Grid<'a> {
+	data: &'a [u8],
+	info: &'a png::OutputInfo,
+	/// Layout of the original image
+	layout: Layout,
+	/// Channels of the scaled image holding the original channels
+	channels: Vec<usize>,
+	scale: usize,
+	x: usize,
+	y: usize,
+	width: usize,
+}
+
+impl Grid<'_> {
+	/// Gets the length of the original rows
+	fn row_len(&self) -> usize { minimum_size(self.layout.0, self.layout.1, self.width as u32, 1) }
+
+	/// Gets a sample of the original image, read at the center of its scaled pixel
+	fn sample(&self, row: usize, sample: usize) -> u16 {
+		let samples = self.channels.len();
+		if sample / samples >= self.width {
+			return 0;
+		}
+		let x = self.x + (sample / samples) * self.scale + self.scale / 2;
+		let y = self.y + row * self.scale + self.scale / 2;
+		let depth = self.info.bit_depth as usize;
+		let bit = (x * self.info.color_type.samples() + self.channels[sample % samples]) * depth;
+		let data = &self.data[y * self.info.line_size..];
+		let value = match depth {
+			16 => u16::from_be_bytes([data[bit / 8], data[bit / 8 + 1]]),
+			_ => (data[bit / 8] >> (8 - depth - bit % 8)) as u16 & ((1 << depth) - 1),
+		};
+		// Lower depths are scaled up to the full range of the scaled image
+		value >> (depth - self.layout.1 as usize)
+	}
+
+	/// Checks whether a byte of the original image data is fully displayed, the padding bits at
+	/// the end of rows are lost when scaling
+	fn is_displayed(&self, i: usize) -> bool {
+		let bits = self.width * self.channels.len() * self.layout.1 as usize;
+		bits.is_multiple_of(8) || i % self.row_len() != self.row_len() - 1
+	}
+
+	/// Gets a byte of the original image data
+	fn byte(&self, i: usize) -> u8 {
+		let (row, col) = (i / self.row_len(), i % self.row_len());
+		match self.layout.1 as usize {
+			16 => (self.sample(row, col / 2) >> (8 * (1 - col % 2))) as u8,
+			depth => (0..8 / depth).fold(0u8, |byte, j| {
+				(((byte as u16) << depth) | self.sample(row, col * 8 / depth + j)) as u8
+			}),
+		}
+	}
+}
+
+/// Gets the layouts an image may have been scaled from, with the channels holding their samples
+fn scaled_layouts(info: &png::OutputInfo) -> Vec<(Layout, Vec<usize>)> {
+	let mut layouts = vec![(
+		(info.color_type, info.bit_depth),
+		(0..info.color_type.samples()).collect(),
+	)];
+	if info.bit_depth == BitDepth::Eight {
+		if info.color_type == ColorType::Rgba {
+			layouts.push(((ColorType::Rgb, BitDepth::Eight), vec![0, 1, 2]));
+			layouts.push(((ColorType::GrayscaleAlpha, BitDepth::Eight), vec![0, 3]));
+		}
+		for depth in [
+			BitDepth::One,
+			BitDepth::Two,
+			BitDepth::Four,
+			BitDepth::Eight,
+		] {
+			if info.color_type != ColorType::Grayscale || depth != BitDepth::Eight {
+				layouts.push(((ColorType::Grayscale, depth), vec![0]));
+			}
+		}
+	}
+	layouts
+}
+
+/// Finds the width and height of the original image from its header copies, returning its data
+fn rescan_grid(grid: &mut Grid, height: usize) -> Option<Image> {
+	let max_width = grid.width;
+	for width in (max_width.saturating_sub(MAX_BORDER / grid.scale).max(1)..=max_width).rev() {
+		grid.width = width;
+		let row_len = grid.row_len();
+		let Ok((header, header_len)) = decode_copy((0..row_len * height).map(|i| grid.byte(i)))
+		else {
+			continue;
+		};
+		if header.version == Version::VERSION_1 {
+			continue;
+		}
+
+		let header_data = (0..header_len).map(|i| grid.byte(i)).collect::<Vec<_>>();
+		for height in (height.saturating_sub(MAX_BORDER / grid.scale).max(1)..=height).rev() {
+			let len = row_len * height;
+			if len < header_len * 4 {
+				break;
+			}
+			let [_, middle, end] = header_copies(len, header_len);
+			if middle
+				.zip(header_data.iter())
+				.chain(end.zip(header_data.iter().rev()))
+				.any(|(i, byte)| grid.is_displayed(i) && grid.byte(i) != *byte)
+			{
+				continue;
+			}
+			let info = png::OutputInfo {
+				width: width as u32,
+				height: height as u32,
+				color_type: grid.layout.0,
+				bit_depth: grid.layout.1,
+				line_size: row_len,
+			};
+			return Some(((0..len).map(|i| grid.byte(i)).collect(), info));
+		}
+	}
+	None
+}
+
+/// Reads the original pixels of an image that was scaled up by an integer factor or framed by
+/// a border, e.g by a screenshot, looking for the header's magic
+pub fn rescan(input: &str, data: &[u8], info: &png::OutputInfo) -> Option<Image> {
+	let magic = Format::Data.magic();
+	let (width, height) = (info.width as usize, info.height as usize);
+	for (layout, channels) in scaled_layouts(info) {
+		for scale in 1..=MAX_SCALE {
+			for y in 0..MAX_BORDER.min(height / scale) {
+				for x in 0..MAX_BORDER.min(width / scale) {
+					// The image was already read as is
+					if (scale, x, y) == (1, 0, 0) && layout == (info.color_type, info.bit_depth) {
+						continue;
+					}
+					let mut grid = Grid {
+						data,
+						info,
+						layout,
+						channels: channels.clone(),
+						scale,
+						x,
+						y,
+						width: (width - x) / scale,
+					};
+					if grid.row_len() < magic.len()
+						|| (0..magic.len()).any(|i| grid.byte(i) != magic[i])
+					{
+						continue;
+					}
+					if let Some(found) = rescan_grid(&mut grid, (height - y) / scale) {
+						eprintln!(
+							"Warning: `{input}` holds a {}x{} image scaled {scale}x at {x},{y}, reading its original pixels",
+							found.1.width, found.1.height
+						);
+						return Some(found);
+					}
+				}
+			}
+		}
+	}
+	None
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::Rng;
+	use rand::SeedableRng;
+	use rand_chacha::ChaCha8Rng;
+
+	use super::*;
+	use crate::header::Encode;
+	use crate::header::Header;
+	use crate::shape::best_layout;
+	use crate::shape::bits_per_pixel;
+
+	/// Lays out the header copies in random image data, as png_data does
+	fn image(layout: Layout) -> Image {
+		let mut payload = vec![0; 3000];
+		ChaCha8Rng::seed_from_u64(2).fill(payload.as_mut_slice());
+		let header = Header::new(Format::Data, Version::CURRENT, &payload, vec![]).unwrap();
+		let mut header_data = vec![];
+		header.encode(&mut header_data);
+
+		let (width, height) = best_layout(
+			(payload.len() + header_data.len() * 3) as u64,
+			bits_per_pixel(layout.0, layout.1),
+		);
+		let len = minimum_size(layout.0, layout.1, width, height);
+		let mut data = vec![0; len];
+		ChaCha8Rng::seed_from_u64(3).fill(data.as_mut_slice());
+		let [start, middle, end] = header_copies(len, header_data.len());
+		data[start].copy_from_slice(&header_data);
+		data[middle].copy_from_slice(&header_data);
+		header_data.reverse();
+		data[end].copy_from_slice(&header_data);
+		let info = png::OutputInfo {
+			width,
+			height,
+			color_type: layout.0,
+			bit_depth: layout.1,
+			line_size: minimum_size(layout.0, layout.1, width, 1),
+		};
+		(data, info)
+	}
+
+	/// Draws an image as rgb8, scaled up and framed by a white border, as screenshots do
+	fn screenshot((data, info): &Image, scale: usize, border: usize) -> Image {
+		let (width, height) = (info.width as usize, info.height as usize);
+		let depth = info.bit_depth as usize;
+		let pixel = |x: usize, y: usize| match info.color_type {
+			ColorType::Rgb => data[y * info.line_size + x * 3..][..3].try_into().unwrap(),
+			_ => {
+				let byte = data[y * info.line_size + x * depth / 8];
+				let value = (byte >> (8 - depth - x * depth % 8)) & ((1 << depth) - 1);
+				[(value as usize * 255 / ((1 << depth) - 1)) as u8; 3]
+			}
+		};
+
+		let screen = png::OutputInfo {
+			width: (width * scale + border * 2) as u32,
+			height: (height * scale + border * 2) as u32,
+			color_type: ColorType::Rgb,
+			bit_depth: BitDepth::Eight,
+			line_size: (width * scale + border * 2) * 3,
+		};
+		let mut shot = vec![0xFF; screen.line_size * screen.height as usize];
+		for y in 0..height * scale {
+			for x in 0..width * scale {
+				let i = (y + border) * screen.line_size + (x + border) * 3;
+				shot[i..i + 3].copy_from_slice(&pixel(x / scale, y / scale));
+			}
+		}
+		(shot, screen)
+	}
+
+	#[test]
+	fn rescans() {
+		for layout in [
+			(ColorType::Rgb, BitDepth::Eight),
+			(ColorType::Grayscale, BitDepth::One),
+			(ColorType::Grayscale, BitDepth::Two),
+			(ColorType::Grayscale, BitDepth::Four),
+		] {
+			let image = image(layout);
+			for (scale, border) in [(1, 0), (2, 0), (1, 3), (2, 5), (3, 17)] {
+				if (layout.0, scale, border) == (ColorType::Rgb, 1, 0) {
+					continue;
+				}
+				let (shot, info) = screenshot(&image, scale, border);
+				let (data, found) = rescan("test", &shot, &info)
+					.unwrap_or_else(|| panic!("{layout:?} {scale}x +{border}"));
+				assert_eq!((found.color_type, found.bit_depth), layout);
+				assert_eq!((found.width, found.height), (image.1.width, image.1.height));
+				assert_eq!(data, image.0, "{layout:?} {scale}x +{border}");
+			}
+		}
+	}
+}
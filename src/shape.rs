@@ -0,0 +1,215 @@
+use png::BitDepth;
+use png::ColorType;
+
+/// Color type and bit depth of an image
+pub type Layout = (ColorType, BitDepth);
+
+/// Gets the number of bits of a pixel
+pub fn bits_per_pixel(colors: ColorType, depth: BitDepth) -> u8 {
+	match colors {
+		ColorType::Rgb => depth as u8 * 3,
+		ColorType::Rgba => depth as u8 * 4,
+		ColorType::Grayscale => depth as u8,
+		ColorType::GrayscaleAlpha => depth as u8 * 2,
+		ColorType::Indexed => depth as u8,
+	}
+}
+
+/// Gets the minimum image buffer size in bytes
+pub fn minimum_size(color: ColorType, depth: BitDepth, width: u32, height: u32) -> usize {
+	let samples = width as usize * color.samples();
+	(match depth {
+		BitDepth::Sixteen => samples * 2,
+		BitDepth::Eight => samples,
+		subbyte => {
+			let samples_per_byte = 8 / subbyte as usize;
+			let whole = samples / samples_per_byte;
+			let fract = usize::from(!samples.is_multiple_of(samples_per_byte));
+			whole + fract
+		}
+	}) * height as usize
+}
+
+/// Largest width or height written to an image, most viewers and libraries refuse bigger images
+pub const MAX_DIMENSION: u32 = 32768;
+
+/// Gets the number of pixels in a byte of sub-byte layouts, their widths being multiples of it so
+/// that rows end on whole bytes. Padding bits are lost when the image is re-saved or scaled
+pub fn pixels_per_byte(bits_per_pixel: u8) -> u64 { (8 / bits_per_pixel as u64).max(1) }
+
+/// Gets the width and height of the near-square image holding `size` bytes
+pub fn best_layout(size: u64, bits_per_pixel: u8) -> (u32, u32) {
+	let sz = (size * 8).div_ceil(bits_per_pixel as u64);
+	// Round up so that the height never exceeds the width
+	let mut width = sz.isqrt();
+	if width * width < sz {
+		width += 1;
+	}
+	let width = width
+		.next_multiple_of(pixels_per_byte(bits_per_pixel))
+		.max(1);
+	(width as u32, sz.div_ceil(width) as u32)
+}
+
+/// Shape of the images, near-square unless `--width`, `--aspect` or `--canvas` is given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+	Square,
+	/// Fixed width, the height follows the data
+	Width(u32),
+	/// Ratio of the width to the height
+	Aspect(u32, u32),
+	/// Fixed width and height, the pixels after the data being padding
+	Canvas(u32, u32),
+}
+
+impl Shape {
+	/// Gets the height keeping the aspect ratio of a width, rounded up
+	fn aspect_height(width: u64, ratio: (u32, u32)) -> u64 {
+		(width * ratio.1 as u64).div_ceil(ratio.0 as u64)
+	}
+
+	/// Gets the width and height of an image holding `size` bytes
+	pub fn layout(self, size: u64, bits_per_pixel: u8) -> (u32, u32) {
+		let sz = (size * 8).div_ceil(bits_per_pixel as u64).max(1);
+		match self {
+			Shape::Square => best_layout(size, bits_per_pixel),
+			Shape::Width(width) => (width, sz.div_ceil(width as u64) as u32),
+			Shape::Aspect(w, h) => {
+				// Smallest width whose height holds the data
+				let align = pixels_per_byte(bits_per_pixel);
+				let mut width = ((sz as u128 * w as u128 / h as u128).isqrt() as u64)
+					.next_multiple_of(align)
+					.max(align);
+				while width * Self::aspect_height(width, (w, h)) < sz {
+					width += align;
+				}
+				(width as u32, Self::aspect_height(width, (w, h)) as u32)
+			}
+			Shape::Canvas(width, height) => (width, height),
+		}
+	}
+
+	/// Gets the number of pixels of the largest image at most `max_dimension` wide and high
+	pub fn max_pixels(self, max_dimension: u32, bits_per_pixel: u8) -> u64 {
+		let max = max_dimension as u64;
+		let align = pixels_per_byte(bits_per_pixel);
+		match self {
+			Shape::Square => (max / align * align).pow(2),
+			Shape::Width(width) => width as u64 * max,
+			Shape::Aspect(w, h) => {
+				let width = max.min(max * w as u64 / h as u64) / align * align;
+				width * Self::aspect_height(width, (w, h))
+			}
+			Shape::Canvas(width, height) => width as u64 * height as u64,
+		}
+	}
+
+	/// Checks that the rows of sub-byte layouts end on whole bytes
+	pub fn check(self, bits_per_pixel: u8) -> Result<(), String> {
+		let align = pixels_per_byte(bits_per_pixel);
+		match self {
+			Shape::Width(width) | Shape::Canvas(width, _)
+				if !(width as u64).is_multiple_of(align) =>
+			{
+				Err(format!(
+					"Invalid width {width}, {bits_per_pixel}-bit layouts need a multiple of {align} pixels"
+				))
+			}
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Padding of the pixels after the data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Padding {
+	/// Random bytes seeded from the header and payload, identical inputs give identical images
+	Hash,
+	Zero,
+	/// Random bytes differing on every run
+	Random,
+}
+
+impl core::str::FromStr for Padding {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"hash" => Ok(Padding::Hash),
+			"zero" => Ok(Padding::Zero),
+			"random" => Ok(Padding::Random),
+			_ => Err(format!(
+				"Unknown padding: `{s}`, expected hash, zero or random"
+			)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sub_byte_widths() {
+		let shape = Shape::Width(161);
+		assert!(shape.check(1).is_err());
+		assert!(shape.check(8).is_ok());
+		assert!(Shape::Canvas(162, 100).check(4).is_ok());
+		assert!(Shape::Canvas(162, 100).check(2).is_err());
+		for bits_per_pixel in [1, 2, 4] {
+			for size in [1, 100, 3000, 3001, 100_000] {
+				for shape in [Shape::Square, Shape::Aspect(16, 9), Shape::Aspect(1, 3)] {
+					let (width, height) = shape.layout(size, bits_per_pixel);
+					assert_eq!(
+						width as u64 * bits_per_pixel as u64 % 8,
+						0,
+						"{shape:?} {size}"
+					);
+					assert!(width as u64 * height as u64 * bits_per_pixel as u64 >= size * 8);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn shape_layouts() {
+		// 1000 bytes of rgb8 are 334 pixels
+		assert_eq!(Shape::Square.layout(1000, 24), (19, 18));
+		assert_eq!(Shape::Width(100).layout(1000, 24), (100, 4));
+		assert_eq!(Shape::Width(1000).layout(1000, 24), (1000, 1));
+		assert_eq!(Shape::Aspect(16, 9).layout(1000, 24), (24, 14));
+		assert_eq!(Shape::Aspect(1, 1).layout(1000, 24), (19, 19));
+		assert_eq!(Shape::Canvas(64, 48).layout(1000, 24), (64, 48));
+		assert_eq!(Shape::Canvas(64, 48).layout(100_000, 24), (64, 48));
+
+		// The smallest image of the ratio holding the data
+		for size in [1, 10, 1000, 12345, 1 << 20] {
+			for (w, h) in [(16, 9), (9, 16), (3, 1), (1, 7)] {
+				let (width, height) = Shape::Aspect(w, h).layout(size, 8);
+				assert_eq!(height as u64, Shape::aspect_height(width as u64, (w, h)));
+				assert!(width as u64 * height as u64 >= size);
+				let smaller = width as u64 - 1;
+				assert!(smaller * Shape::aspect_height(smaller, (w, h)) < size);
+			}
+		}
+
+		// The largest images stay within the maximum dimension
+		for shape in [
+			Shape::Square,
+			Shape::Width(300),
+			Shape::Aspect(16, 9),
+			Shape::Aspect(1, 5),
+		] {
+			let pixels = shape.max_pixels(1000, 24);
+			let (width, height) = shape.layout(pixels * 3, 24);
+			assert!(
+				width <= 1000 && height <= 1000,
+				"{shape:?}: {width}x{height}"
+			);
+			assert!(width as u64 * height as u64 >= pixels);
+		}
+		assert_eq!(Shape::Width(300).max_pixels(1000, 24), 300_000);
+		assert_eq!(Shape::Aspect(16, 9).max_pixels(1000, 24), 1000 * 563);
+	}
+}
@@ -0,0 +1,102 @@
+use ed25519_dalek::Signer;
+use ed25519_dalek::SigningKey;
+use ed25519_dalek::VerifyingKey;
+
+/// Prefix of Ed25519 public key files
+pub const PUBLIC_KEY_PREFIX: &str = "png_data-ed25519-pub:";
+/// Prefix of Ed25519 secret key files
+pub const SECRET_KEY_PREFIX: &str = "png_data-ed25519-key:";
+
+/// Ed25519 signature of a header and its payload, see [`crate::header::Header::signed_message`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderSignature {
+	/// Public key of the signer
+	pub public_key: [u8; 32],
+	pub signature: [u8; 64],
+}
+
+impl HeaderSignature {
+	/// Signs a message with a secret key
+	pub fn sign(secret: &[u8; 32], message: &[u8]) -> Self {
+		let key = SigningKey::from_bytes(secret);
+		Self {
+			public_key: key.verifying_key().to_bytes(),
+			signature: key.sign(message).to_bytes(),
+		}
+	}
+
+	/// Verifies the signature of a message, and that it was made by `signer` when given
+	pub fn verify(&self, message: &[u8], signer: Option<&[u8; 32]>) -> Result<(), String> {
+		VerifyingKey::from_bytes(&self.public_key)
+			.and_then(|key| {
+				key.verify_strict(
+					message,
+					&ed25519_dalek::Signature::from_bytes(&self.signature),
+				)
+			})
+			.map_err(|_| "Invalid signature, the image was modified after signing".to_string())?;
+		match signer {
+			Some(signer) if *signer != self.public_key => Err(format!(
+				"The image was signed by {}, not by the given key {}",
+				fingerprint(&self.public_key),
+				fingerprint(signer)
+			)),
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Short fingerprint of a public key
+pub fn fingerprint(public_key: &[u8; 32]) -> String {
+	blake3::hash(public_key).as_bytes()[..16]
+		.iter()
+		.map(|byte| format!("{byte:02x}"))
+		.collect::<Vec<_>>()
+		.join(":")
+}
+
+/// Generates an Ed25519 key pair, returning the secret and public keys
+pub fn generate_keys() -> ([u8; 32], [u8; 32]) {
+	let mut secret = [0u8; 32];
+	rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut secret);
+	let public = SigningKey::from_bytes(&secret).verifying_key();
+	(secret, public.to_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn signatures() {
+		let (secret, public) = generate_keys();
+		let (_, other) = generate_keys();
+		let signature = HeaderSignature::sign(&secret, b"message");
+		assert_eq!(signature.public_key, public);
+		assert!(signature.verify(b"message", None).is_ok());
+		assert!(signature.verify(b"message", Some(&public)).is_ok());
+		assert!(signature.verify(b"message", Some(&other)).is_err());
+		assert!(signature.verify(b"tampered", None).is_err());
+
+		let mut forged = signature.clone();
+		forged.signature[0] ^= 0x01;
+		assert!(forged.verify(b"message", None).is_err());
+
+		// A valid signature does not hold for another key
+		let mut forged = signature.clone();
+		forged.public_key = other;
+		assert!(forged.verify(b"message", None).is_err());
+	}
+
+	#[test]
+	fn fingerprints() {
+		let (_, public) = generate_keys();
+		let (_, other) = generate_keys();
+		let text = fingerprint(&public);
+		assert_eq!(text.split(':').count(), 16);
+		assert!(text
+			.split(':')
+			.all(|byte| byte.len() == 2 && u8::from_str_radix(byte, 16).is_ok()));
+		assert_ne!(text, fingerprint(&other));
+	}
+}
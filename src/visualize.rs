@@ -0,0 +1,191 @@
+/// Largest number of pixels of a visualization, larger files map several bytes to each pixel
+const MAX_PIXELS: usize = 1 << 20;
+/// Bytes around each pixel over which the local entropy is measured
+const ENTROPY_WINDOW: usize = 32;
+
+/// Coloring of the bytes of a visualization
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coloring {
+	/// Black for 0x00, white for 0xFF, blue for printable ASCII, green for other ASCII and red
+	/// for the rest
+	Class,
+	/// Local Shannon entropy, from black to blue then pink
+	Entropy,
+}
+
+impl core::str::FromStr for Coloring {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"class" => Ok(Coloring::Class),
+			"entropy" => Ok(Coloring::Entropy),
+			_ => Err(format!(
+				"Unknown coloring: `{s}`, expected class or entropy"
+			)),
+		}
+	}
+}
+
+/// Color of a byte's class
+fn class_color(byte: u8) -> [u8; 3] {
+	match byte {
+		0x00 => [0x00, 0x00, 0x00],
+		0xFF => [0xFF, 0xFF, 0xFF],
+		0x20..=0x7E => [0x37, 0x7E, 0xB8],
+		0x01..=0x7F => [0x4D, 0xAF, 0x4A],
+		_ => [0xE4, 0x1A, 0x1C],
+	}
+}
+
+/// Color of an entropy between 0 and 1
+fn entropy_color(entropy: f32) -> [u8; 3] {
+	let curve = |v: f32| (4.0 * v - 4.0 * v * v).powi(4).max(0.0);
+	let red = if entropy > 0.5 {
+		curve(entropy - 0.5)
+	} else {
+		0.0
+	};
+	[(red * 255.0) as u8, 0, (entropy * entropy * 255.0) as u8]
+}
+
+/// Gets the position of the `index`-th point of a Hilbert curve filling a `side` by `side`
+/// square, `side` being a power of two
+pub fn hilbert_point(side: u32, index: u64) -> (u32, u32) {
+	let (mut x, mut y) = (0u32, 0u32);
+	let mut index = index;
+	let mut size = 1;
+	while size < side {
+		let rx = ((index / 2) & 1) as u32;
+		let ry = ((index ^ rx as u64) & 1) as u32;
+		if ry == 0 {
+			if rx == 1 {
+				x = size - 1 - x;
+				y = size - 1 - y;
+			}
+			std::mem::swap(&mut x, &mut y);
+		}
+		x += size * rx;
+		y += size * ry;
+		index /= 4;
+		size *= 2;
+	}
+	(x, y)
+}
+
+/// RGB picture of a file's structure, each pixel shows one or more consecutive bytes
+pub struct Visualization {
+	pub width: u32,
+	pub height: u32,
+	/// RGB pixels, black past the end of the file
+	pub pixels: Vec<u8>,
+}
+
+impl Visualization {
+	/// Renders a file, laying its bytes along a Hilbert curve or row by row
+	pub fn new(data: &[u8], coloring: Coloring, hilbert: bool) -> Result<Self, String> {
+		if data.is_empty() {
+			return Err("Can not visualize an empty file".into());
+		}
+		let chunk = data.len().div_ceil(MAX_PIXELS);
+		let count = data.len().div_ceil(chunk);
+
+		let side = count.isqrt() + usize::from(count.isqrt().pow(2) < count);
+		let (width, height) = if hilbert {
+			let side = side.next_power_of_two();
+			(side, side)
+		} else {
+			(side, count.div_ceil(side))
+		};
+
+		let mut pixels = vec![0u8; width * height * 3];
+		for (index, start) in (0..data.len()).step_by(chunk).enumerate() {
+			let bytes = &data[start..data.len().min(start + chunk)];
+			let color = match coloring {
+				Coloring::Class => {
+					let mut sum = [0usize; 3];
+					for byte in bytes {
+						for (sum, channel) in sum.iter_mut().zip(class_color(*byte)) {
+							*sum += channel as usize;
+						}
+					}
+					sum.map(|sum| (sum / bytes.len()) as u8)
+				}
+				Coloring::Entropy => {
+					let len = chunk.max(ENTROPY_WINDOW).min(data.len());
+					let from = (start + chunk / 2)
+						.saturating_sub(len / 2)
+						.min(data.len() - len);
+					let window = &data[from..from + len];
+					// Normalize by the highest entropy the window can have
+					let max = (window.len().min(256) as f32).log2().max(1.0);
+					entropy_color(entropy::shannon_entropy(window) / max)
+				}
+			};
+
+			let (x, y) = if hilbert {
+				hilbert_point(width as u32, index as u64)
+			} else {
+				((index % width) as u32, (index / width) as u32)
+			};
+			let pos = (y as usize * width + x as usize) * 3;
+			pixels[pos..pos + 3].copy_from_slice(&color);
+		}
+
+		Ok(Self {
+			width: width as u32,
+			height: height as u32,
+			pixels,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn hilbert() {
+		for side in [1, 2, 4, 32] {
+			let mut seen = vec![false; (side * side) as usize];
+			let mut last = None;
+			for index in 0..(side * side) as u64 {
+				let (x, y) = hilbert_point(side, index);
+				assert!(!std::mem::replace(&mut seen[(y * side + x) as usize], true));
+				// Consecutive points are neighbours
+				if let Some((lx, ly)) = last {
+					assert_eq!(x.abs_diff(lx) + y.abs_diff(ly), 1);
+				}
+				last = Some((x, y));
+			}
+		}
+	}
+
+	#[test]
+	fn coloring() {
+		let vis = Visualization::new(b"\x00\xFFa\x01\x80", Coloring::Class, false).unwrap();
+		assert_eq!((vis.width, vis.height), (3, 2));
+		assert_eq!(
+			vis.pixels,
+			[
+				0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0x37, 0x7E, 0xB8, 0x4D, 0xAF, 0x4A, 0xE4, 0x1A,
+				0x1C, 0x00, 0x00, 0x00
+			]
+		);
+
+		let mut data = vec![0u8; 4096];
+		data[2048..]
+			.iter_mut()
+			.enumerate()
+			.for_each(|(i, byte)| *byte = i as u8);
+		let vis = Visualization::new(&data, Coloring::Entropy, true).unwrap();
+		assert_eq!((vis.width, vis.height), (64, 64));
+		// Zeros have no entropy, a ramp of distinct bytes has the highest
+		assert_eq!(vis.pixels[..3], [0, 0, 0]);
+		let (x, y) = hilbert_point(64, 4095);
+		let pos = (y as usize * 64 + x as usize) * 3;
+		assert_eq!(vis.pixels[pos..pos + 3], [255, 0, 255]);
+
+		assert!(Visualization::new(b"", Coloring::Class, true).is_err());
+	}
+}
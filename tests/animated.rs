@@ -0,0 +1,38 @@
+mod common;
+
+use std::fs::File;
+
+use common::payload;
+use common::png_data;
+use common::round_trip;
+use common::TempDir;
+
+/// Reads the number of frames of an animated image, and checks that every frame decodes
+fn frames(path: &str) -> u32 {
+	let mut reader = png::Decoder::new(File::open(path).unwrap())
+		.read_info()
+		.unwrap();
+	let frames = reader.info().animation_control.unwrap().num_frames;
+	let mut data = vec![0; reader.output_buffer_size()];
+	for _ in 0..frames {
+		reader.next_frame(&mut data).unwrap();
+	}
+	frames
+}
+
+#[test]
+fn roundtrip() {
+	let dir = TempDir::new("animated");
+	let data = payload(20_000);
+	for (layout, count) in [("rgb8", "2"), ("g1", "5"), ("rgba16", "3")] {
+		let image = round_trip(&dir, &data, &["-l", layout, "--frames", count]);
+		assert_eq!(frames(&image).to_string(), count, "{layout}");
+	}
+
+	// Upgrading keeps the frames
+	let (upgraded, output) = (dir.path("upgraded.png"), dir.path("output.bin"));
+	png_data(&["--upgrade", &dir.path("image.png"), "-o", &upgraded]);
+	assert_eq!(frames(&upgraded), 3);
+	png_data(&["-d", &upgraded, "-o", &output]);
+	assert_eq!(std::fs::read(&output).unwrap(), data);
+}
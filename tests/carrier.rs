@@ -0,0 +1,68 @@
+mod common;
+
+use std::fs::File;
+
+use common::payload;
+use common::png_data;
+use common::png_data_err;
+use common::round_trip;
+use common::TempDir;
+
+/// Writes an ordinary rgb8 image
+fn write_logo(path: &str) {
+	let mut encoder = png::Encoder::new(File::create(path).unwrap(), 40, 30);
+	encoder.set_color(png::ColorType::Rgb);
+	encoder.set_depth(png::BitDepth::Eight);
+	let mut writer = encoder.write_header().unwrap();
+	writer.write_image_data(&payload(40 * 30 * 3)).unwrap();
+}
+
+/// Reads the pixels of an image
+fn pixels(path: &str) -> Vec<u8> {
+	let mut reader = png::Decoder::new(File::open(path).unwrap())
+		.read_info()
+		.unwrap();
+	let mut data = vec![0; reader.output_buffer_size()];
+	reader.next_frame(&mut data).unwrap();
+	data
+}
+
+/// Removes the chunks of a type from a png file, as programs re-saving images do
+fn strip_chunks(path: &str, kind: &[u8]) {
+	let file = std::fs::read(path).unwrap();
+	let mut stripped = file[..8].to_vec();
+	let mut rest = &file[8..];
+	while !rest.is_empty() {
+		let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+		if &rest[4..8] != kind {
+			stripped.extend_from_slice(&rest[..12 + len]);
+		}
+		rest = &rest[12 + len..];
+	}
+	std::fs::write(path, stripped).unwrap();
+}
+
+#[test]
+fn roundtrip() {
+	let dir = TempDir::new("carrier");
+	let (logo, output) = (dir.path("logo.png"), dir.path("output.bin"));
+	write_logo(&logo);
+
+	let image = round_trip(&dir, &payload(5000), &["--carrier", &logo]);
+	assert_eq!(pixels(&image), pixels(&logo));
+	let info = png_data(&["-z", &image]);
+	assert!(info.contains("Data: 5000bytes"), "{info}");
+
+	// Ordinary images are not png_data images, without hinting at carriers
+	let error = png_data_err(&["-d", &logo, "-o", &output]);
+	assert!(error.contains("this is not a png_data image"), "{error}");
+	assert!(!error.contains("pdTa"), "{error}");
+
+	// A carrier that lost its chunks is told apart
+	strip_chunks(&image, b"pdTa");
+	let error = png_data_err(&["-d", &image, "-o", &output]);
+	assert!(
+		error.contains("was written as a carrier image but has no `pdTa` chunk"),
+		"{error}"
+	);
+}
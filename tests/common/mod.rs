@@ -0,0 +1,118 @@
+//! Helpers running the built tools on temporary files
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::path::PathBuf;
+use std::process::Command;
+
+use rand::Rng;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+/// Temporary directory, removed when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+	pub fn new(name: &str) -> Self {
+		let dir = std::env::temp_dir().join(format!("png_data-{name}-{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir).unwrap();
+		Self(dir)
+	}
+
+	/// Gets the path of a file in the directory
+	pub fn path(&self, name: &str) -> String { self.0.join(name).to_string_lossy().into_owned() }
+}
+
+impl Drop for TempDir {
+	fn drop(&mut self) { let _ = std::fs::remove_dir_all(&self.0); }
+}
+
+/// Path of a test fixture
+pub fn fixture(name: &str) -> String {
+	format!("{}/tests/fixtures/{name}", env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Gets `len` pseudo-random bytes, the same on every run
+pub fn payload(len: usize) -> Vec<u8> {
+	let mut data = vec![0; len];
+	ChaCha8Rng::seed_from_u64(len as u64).fill(data.as_mut_slice());
+	data
+}
+
+/// Encodes `data` to `image.png` in a directory with `args`, then checks that it decodes back.
+/// Returns the path of the image
+pub fn round_trip(dir: &TempDir, data: &[u8], args: &[&str]) -> String {
+	let (input, image, output) = (
+		dir.path("input.bin"),
+		dir.path("image.png"),
+		dir.path("output.bin"),
+	);
+	std::fs::write(&input, data).unwrap();
+	png_data(&[&["-e", &input, "-o", &image], args].concat());
+	png_data(&["-d", &image, "-o", &output]);
+	assert_eq!(std::fs::read(&output).unwrap(), data, "{args:?}");
+	std::fs::remove_file(&output).unwrap();
+	image
+}
+
+/// Runs a tool, returning its standard error or failing with it
+fn run(tool: &str, args: &[&str], envs: &[(&str, &str)], success: bool) -> String {
+	let output = Command::new(tool)
+		.args(args)
+		.envs(envs.iter().copied())
+		.output()
+		.unwrap();
+	let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+	assert_eq!(
+		output.status.success(),
+		success,
+		"`{tool} {}` {}: {stderr}",
+		args.join(" "),
+		if success { "failed" } else { "succeeded" }
+	);
+	stderr
+}
+
+/// Runs `png_data`, which must succeed
+pub fn png_data(args: &[&str]) -> String { run(env!("CARGO_BIN_EXE_png_data"), args, &[], true) }
+
+/// Runs `png_data` with environment variables, which must succeed
+pub fn png_data_env(args: &[&str], envs: &[(&str, &str)]) -> String {
+	run(env!("CARGO_BIN_EXE_png_data"), args, envs, true)
+}
+
+/// Runs `png_data`, which must fail
+pub fn png_data_err(args: &[&str]) -> String {
+	run(env!("CARGO_BIN_EXE_png_data"), args, &[], false)
+}
+
+/// Runs `png_embed`, which must succeed
+pub fn png_embed(args: &[&str]) -> String { run(env!("CARGO_BIN_EXE_png_embed"), args, &[], true) }
+
+/// Inverts the image bytes in `range`, keeping the image format
+pub fn damage(path: &str, range: std::ops::Range<usize>) {
+	let decoder = png::Decoder::new(File::open(path).unwrap());
+	let mut reader = decoder.read_info().unwrap();
+	let mut data = vec![0; reader.output_buffer_size()];
+	reader.next_frame(&mut data).unwrap();
+	let info = reader.info().clone();
+	data[range].iter_mut().for_each(|byte| *byte ^= 0xFF);
+
+	let mut encoder = png::Encoder::new(File::create(path).unwrap(), info.width, info.height);
+	encoder.set_color(info.color_type);
+	encoder.set_depth(info.bit_depth);
+	if let Some(palette) = info.palette {
+		encoder.set_palette(palette.into_owned());
+	}
+	let mut writer = encoder.write_header().unwrap();
+	writer.write_image_data(&data).unwrap();
+}
+
+/// Reads the width and height of an image
+pub fn dimensions(path: &str) -> (u32, u32) {
+	let reader = png::Decoder::new(File::open(path).unwrap())
+		.read_info()
+		.unwrap();
+	(reader.info().width, reader.info().height)
+}
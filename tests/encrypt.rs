@@ -0,0 +1,83 @@
+mod common;
+
+use common::payload;
+use common::png_data;
+use common::png_data_err;
+use common::TempDir;
+
+#[test]
+fn passphrase() {
+	let dir = TempDir::new("encrypt");
+	let (input, image, output) = (
+		dir.path("input.bin"),
+		dir.path("image.png"),
+		dir.path("output.bin"),
+	);
+	let (passphrase, wrong) = (dir.path("passphrase"), dir.path("wrong"));
+	std::fs::write(&passphrase, "hunter2\n").unwrap();
+	std::fs::write(&wrong, "hunter3\n").unwrap();
+	let data = payload(50_000);
+	std::fs::write(&input, &data).unwrap();
+
+	// The payload is encrypted once, then split
+	png_data(&[
+		"-e",
+		&input,
+		"-o",
+		&image,
+		"-l",
+		"rgb8",
+		"-m",
+		"mime=application/octet-stream",
+		"--encrypt",
+		"--passphrase-file",
+		&passphrase,
+		"--max-bytes",
+		"30000",
+	]);
+	let first = dir.path("image.001.png");
+	png_data(&[
+		"-d",
+		&first,
+		"-o",
+		&output,
+		"--passphrase-file",
+		&passphrase,
+	]);
+	assert_eq!(std::fs::read(&output).unwrap(), data);
+
+	let err = png_data_err(&["-d", &first, "-o", &output, "--passphrase-file", &wrong]);
+	assert!(err.contains("Wrong passphrase"), "{err}");
+}
+
+#[test]
+fn recipients() {
+	let dir = TempDir::new("recipients");
+	let (input, image, output, key) = (
+		dir.path("input.bin"),
+		dir.path("image.png"),
+		dir.path("output.bin"),
+		dir.path("key"),
+	);
+	png_data(&["--keygen", &key]);
+	let data = payload(5000);
+	std::fs::write(&input, &data).unwrap();
+
+	png_data(&[
+		"-e",
+		&input,
+		"-o",
+		&image,
+		"-l",
+		"rgb8",
+		"--fec",
+		"10",
+		"-r",
+		&dir.path("key.pub"),
+	]);
+	png_data(&["-d", &image, "-o", &output, "--identity", &key]);
+	assert_eq!(std::fs::read(&output).unwrap(), data);
+
+	let err = png_data_err(&["-d", &image, "-o", &output]);
+	assert!(err.contains("--identity"), "{err}");
+}
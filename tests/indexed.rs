@@ -0,0 +1,25 @@
+mod common;
+
+use std::fs::File;
+
+use common::payload;
+use common::round_trip;
+use common::TempDir;
+
+#[test]
+fn indexed_layouts() {
+	let dir = TempDir::new("indexed");
+	let data = payload(3000);
+	for (layout, depth) in [("p1", 1), ("p2", 2), ("p4", 4), ("p8", 8)] {
+		let image = round_trip(&dir, &data, &["-l", layout]);
+
+		// A palette entry for every index
+		let reader = png::Decoder::new(File::open(&image).unwrap())
+			.read_info()
+			.unwrap();
+		let info = reader.info();
+		assert_eq!(info.color_type, png::ColorType::Indexed, "{layout}");
+		assert_eq!(info.bit_depth as u8, depth, "{layout}");
+		assert_eq!(info.palette.as_ref().unwrap().len(), 3 << depth, "{layout}");
+	}
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use common::payload;
+use common::png_data;
+use common::png_data_err;
+use common::TempDir;
+
+/// Writes incompressible data, split over several images by `--max-bytes`
+fn split(dir: &TempDir, args: &[&str]) -> (Vec<u8>, Vec<String>) {
+	let (input, output) = (dir.path("input.bin"), dir.path("out.png"));
+	let data = payload(100_000);
+	std::fs::write(&input, &data).unwrap();
+	let args = [&["-e", &input, "-l", "rgb8", "-o", &output], args].concat();
+	png_data(&args);
+
+	let mut parts = std::fs::read_dir(dir.path(""))
+		.unwrap()
+		.map(|entry| entry.unwrap().path().to_string_lossy().into_owned())
+		.filter(|path| path.ends_with(".png"))
+		.collect::<Vec<_>>();
+	parts.sort();
+	(data, parts)
+}
+
+#[test]
+fn reassembly() {
+	let dir = TempDir::new("parts");
+	let (data, parts) = split(&dir, &["--max-bytes", "30000"]);
+	assert_eq!(
+		parts
+			.iter()
+			.map(|part| &part[part.len() - 12..])
+			.collect::<Vec<_>>(),
+		[
+			"/out.001.png",
+			"/out.002.png",
+			"/out.003.png",
+			"/out.004.png"
+		]
+	);
+	for part in &parts {
+		assert!(std::fs::metadata(part).unwrap().len() <= 30000, "{part}");
+	}
+
+	// Parts are given in any order
+	let output = dir.path("output.bin");
+	png_data(&[
+		"-d", &parts[2], &parts[0], &parts[3], &parts[1], "-o", &output,
+	]);
+	assert_eq!(std::fs::read(&output).unwrap(), data);
+
+	// The other parts are found next to any of them
+	std::fs::remove_file(&output).unwrap();
+	png_data(&["-d", &parts[1], "-o", &output]);
+	assert_eq!(std::fs::read(&output).unwrap(), data);
+
+	// Missing parts are named
+	std::fs::remove_file(&parts[2]).unwrap();
+	let error = png_data_err(&["-d", &parts[0], "-o", &output]);
+	assert!(
+		error.contains(&format!("Missing part(s) `{}` out of 4", parts[2])),
+		"{error}"
+	);
+}
+
+#[test]
+fn fountain_subset() {
+	let dir = TempDir::new("parts-fountain");
+	let (data, parts) = split(&dir, &["--fountain", "3/5"]);
+	assert_eq!(parts.len(), 5);
+
+	// Any 3 of the 5 images decode
+	let output = dir.path("output.bin");
+	png_data(&["-d", &parts[4], &parts[1], &parts[3], "-o", &output]);
+	assert_eq!(std::fs::read(&output).unwrap(), data);
+	for part in &parts[..2] {
+		std::fs::remove_file(part).unwrap();
+	}
+	std::fs::remove_file(&output).unwrap();
+	png_data(&["-d", &parts[2], "-o", &output]);
+	assert_eq!(std::fs::read(&output).unwrap(), data);
+}
+
+#[test]
+fn shapes_split_only_when_asked() {
+	let dir = TempDir::new("parts-shapes");
+	let (input, output) = (dir.path("input.bin"), dir.path("out.png"));
+	std::fs::write(&input, vec![5; 20_000]).unwrap();
+	for (option, value) in [
+		("--width", "50"),
+		("--aspect", "16:9"),
+		("--canvas", "60x60"),
+	] {
+		let error = png_data_err(&[
+			"-e",
+			&input,
+			"-l",
+			"rgb8",
+			option,
+			value,
+			"--max-dimension",
+			"60",
+			"-o",
+			&output,
+		]);
+		assert!(error.contains("The file needs 20000bytes but "), "{error}");
+		assert!(
+			std::fs::read_dir(dir.path("")).unwrap().count() == 1,
+			"{option}"
+		);
+	}
+
+	// Square images are split to fit the maximum dimension
+	png_data(&[
+		"-e",
+		&input,
+		"-l",
+		"rgb8",
+		"--max-dimension",
+		"60",
+		"-o",
+		&output,
+	]);
+	assert!(std::path::Path::new(&dir.path("out.002.png")).exists());
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use std::fs::File;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use common::payload;
+use common::png_data;
+use common::png_data_env;
+use common::TempDir;
+
+/// Sets the modification time of a file, in seconds since the epoch
+fn touch(path: &str, secs: u64) {
+	File::options()
+		.write(true)
+		.open(path)
+		.unwrap()
+		.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+		.unwrap();
+}
+
+#[test]
+fn mtimes() {
+	let dir = TempDir::new("reproducible");
+	let (input, first, second) = (
+		dir.path("input.bin"),
+		dir.path("first.png"),
+		dir.path("second.png"),
+	);
+	std::fs::write(&input, payload(1200)).unwrap();
+
+	// The modification time is stored by default, and restored on decode
+	touch(&input, 1_600_000_000);
+	png_data(&["-e", &input, "-l", "rgb8", "-o", &first]);
+	touch(&input, 1_700_000_000);
+	png_data(&["-e", &input, "-l", "rgb8", "-o", &second]);
+	assert_ne!(
+		std::fs::read(&first).unwrap(),
+		std::fs::read(&second).unwrap()
+	);
+	let info = png_data(&["-z", &second]);
+	assert!(info.contains("Modified: 2023-11-14 22:13:20"), "{info}");
+	let output = dir.path("output.bin");
+	png_data(&["-d", &first, "-o", &output]);
+	assert_eq!(
+		std::fs::metadata(&output).unwrap().modified().unwrap(),
+		SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000)
+	);
+
+	// Clamped to SOURCE_DATE_EPOCH, touching the file keeps the image
+	let epoch = [("SOURCE_DATE_EPOCH", "1500000000")];
+	png_data_env(&["-e", &input, "-l", "rgb8", "-o", &first], &epoch);
+	touch(&input, 1_600_000_000);
+	png_data_env(&["-e", &input, "-l", "rgb8", "-o", &second], &epoch);
+	assert_eq!(
+		std::fs::read(&first).unwrap(),
+		std::fs::read(&second).unwrap()
+	);
+	let info = png_data(&["-z", &second]);
+	assert!(info.contains("Modified: 2017-07-14 02:40:00"), "{info}");
+
+	// Or left out with the other attributes
+	png_data(&["-e", &input, "-l", "rgb8", "--no-attributes", "-o", &first]);
+	touch(&input, 1_700_000_000);
+	png_data(&["-e", &input, "-l", "rgb8", "--no-attributes", "-o", &second]);
+	assert_eq!(
+		std::fs::read(&first).unwrap(),
+		std::fs::read(&second).unwrap()
+	);
+	assert!(!png_data(&["-z", &first]).contains("Modified:"));
+}
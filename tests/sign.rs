@@ -0,0 +1,24 @@
+mod common;
+
+use common::damage;
+use common::payload;
+use common::png_data;
+use common::round_trip;
+use common::TempDir;
+
+#[test]
+fn signer() {
+	let dir = TempDir::new("sign");
+	let key = dir.path("key");
+	png_data(&["--keygen", &key, "--signing"]);
+	let image = round_trip(&dir, &payload(5000), &["-l", "rgb8", "--sign", &key]);
+
+	let info = png_data(&["-z", &image]);
+	assert!(info.contains("Signed by: "), "{info}");
+
+	// A damaged payload cannot be verified, its signer is only shown as claimed
+	damage(&image, 2000..4000);
+	let info = png_data(&["-z", &image]);
+	assert!(info.contains("Unverified signature by: "), "{info}");
+	assert!(!info.contains("Signed by: "), "{info}");
+}
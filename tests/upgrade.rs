@@ -0,0 +1,68 @@
+mod common;
+
+use common::dimensions;
+use common::fixture;
+use common::payload;
+use common::png_data;
+use common::png_embed;
+use common::round_trip;
+use common::TempDir;
+
+/// Payload of the VERSION_1 fixtures, written by the first releases of the tools
+const LEGACY: &[u8] = b"Legacy VERSION_1 image fixture\n";
+
+#[test]
+fn png_data_v1() {
+	let dir = TempDir::new("upgrade-data");
+	let (upgraded, output) = (dir.path("upgraded.png"), dir.path("legacy.txt"));
+	png_data(&["--upgrade", &fixture("data-v1.png"), "-o", &upgraded]);
+
+	let info = png_data(&["-z", &upgraded]);
+	assert!(info.contains("Version: VERSION_2"), "{info}");
+	assert!(info.contains("comment=legacy"), "{info}");
+	png_data(&["-d", &upgraded, "-o", &output]);
+	assert_eq!(std::fs::read(&output).unwrap(), LEGACY);
+	// The larger header grows the image, keeping its width
+	assert_eq!(
+		dimensions(&upgraded).0,
+		dimensions(&fixture("data-v1.png")).0
+	);
+}
+
+#[test]
+fn png_data_shapes() {
+	let dir = TempDir::new("upgrade-shapes");
+	let (upgraded, output) = (dir.path("upgraded.png"), dir.path("output.bin"));
+	let data = payload(1200);
+	for shape in [
+		["--width", "64"],
+		["--aspect", "16:9"],
+		["--canvas", "90x70"],
+	] {
+		let image = round_trip(&dir, &data, &[&["-l", "rgb8"], &shape[..]].concat());
+		png_data(&["--upgrade", &image, "-o", &upgraded]);
+		assert_eq!(dimensions(&upgraded), dimensions(&image), "{shape:?}");
+		png_data(&["-d", &upgraded, "-o", &output]);
+		assert_eq!(std::fs::read(&output).unwrap(), data);
+		std::fs::remove_file(&output).unwrap();
+	}
+}
+
+#[test]
+fn png_embed_v1() {
+	let dir = TempDir::new("upgrade-embed");
+	let (upgraded, output) = (dir.path("upgraded.png"), dir.path("legacy.txt"));
+	png_embed(&[
+		"-l",
+		"lo2",
+		"--upgrade",
+		&fixture("embed-v1.png"),
+		"-o",
+		&upgraded,
+	]);
+
+	let info = png_embed(&["-l", "lo2", "-z", &upgraded]);
+	assert!(info.contains("Version: VERSION_2"), "{info}");
+	png_embed(&["-l", "lo2", "-d", &upgraded, "-o", &output]);
+	assert_eq!(std::fs::read(&output).unwrap(), LEGACY);
+}
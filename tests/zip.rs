@@ -0,0 +1,72 @@
+mod common;
+
+use std::fs::File;
+use std::io::Read;
+
+use common::payload;
+use common::png_data;
+use common::round_trip;
+use common::TempDir;
+
+/// Reads the files of a zip, sorted by name
+fn zip_files(path: &str) -> Vec<(String, Vec<u8>)> {
+	let mut zip = zip::ZipArchive::new(File::open(path).unwrap()).unwrap();
+	let mut files = (0..zip.len())
+		.map(|i| {
+			let mut file = zip.by_index(i).unwrap();
+			let mut data = vec![];
+			file.read_to_end(&mut data).unwrap();
+			(file.name().to_string(), data)
+		})
+		.collect::<Vec<_>>();
+	files.sort();
+	files
+}
+
+/// Checks that an image is a png decoders read to its end
+fn check_png(path: &str) {
+	let mut reader = png::Decoder::new(File::open(path).unwrap())
+		.read_info()
+		.unwrap();
+	let mut data = vec![0; reader.output_buffer_size()];
+	reader.next_frame(&mut data).unwrap();
+	reader.finish().unwrap();
+}
+
+#[test]
+fn polyglot() {
+	let dir = TempDir::new("zip");
+	let data = payload(3600);
+	let image = round_trip(&dir, &data, &["-l", "rgb8", "--zip"]);
+	check_png(&image);
+	assert_eq!(zip_files(&image), [("input.bin".to_string(), data)]);
+}
+
+#[test]
+fn polyglot_archive() {
+	let dir = TempDir::new("zip-archive");
+	let files = dir.path("files");
+	std::fs::create_dir_all(format!("{files}/sub")).unwrap();
+	std::fs::write(format!("{files}/a.txt"), b"first").unwrap();
+	std::fs::write(format!("{files}/sub/b.txt"), b"second").unwrap();
+	let (image, output) = (dir.path("image.png"), dir.path("output"));
+
+	png_data(&["-e", &files, "-l", "rgb8", "--zip", "-o", &image]);
+	check_png(&image);
+	png_data(&["-d", &image, "-o", &output]);
+	assert_eq!(
+		std::fs::read(format!("{output}/files/sub/b.txt")).unwrap(),
+		b"second"
+	);
+	let names = zip_files(&image)
+		.into_iter()
+		.filter(|(name, _)| !name.ends_with('/'))
+		.collect::<Vec<_>>();
+	assert_eq!(
+		names,
+		[
+			("files/a.txt".to_string(), b"first".to_vec()),
+			("files/sub/b.txt".to_string(), b"second".to_vec()),
+		]
+	);
+}